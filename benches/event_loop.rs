@@ -0,0 +1,211 @@
+//! Event loop benchmark: wake-up latency, idle wake-ups, watch registration
+//! and debouncing cost.
+//!
+//! Run with `cargo bench --bench event_loop`. Everything is measured on the
+//! parts the main loop is made of: a [`KqueueWatcher`] polled with the timeout
+//! of a [`Debouncer`], which decides when a changed file is read.
+
+use kqueue2::Ident;
+use lw::consts::{DEBOUNCE_MS, HOUSEKEEPING_INTERVAL_MS};
+use lw::debounce::Debouncer;
+use lw::watcher::KqueueWatcher;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+
+/// Appends measured for the latency benchmark.
+const LATENCY_SAMPLES: usize = 50;
+
+/// How long the idle benchmark watches a quiet file.
+const IDLE_PERIOD: Duration = Duration::from_secs(3);
+
+/// Files registered by the registration benchmark.
+const REGISTERED_FILES: usize = 1_000;
+
+/// Events pushed through the debouncer.
+const DEBOUNCED_EVENTS: usize = 1_000_000;
+
+/// Distinct paths those events are spread over.
+const DEBOUNCED_PATHS: usize = 1_000;
+
+
+fn main() {
+    let directory = std::env::temp_dir().join(format!("lw_bench_{}", std::process::id()));
+    fs::create_dir_all(&directory).expect("Couldn't create benchmark directory");
+    let path = directory.join("watched.log");
+    fs::write(&path, "").expect("Couldn't create benchmark file");
+    let mut watcher = KqueueWatcher::new(0).expect("Could not create kqueue watcher!");
+    register(&mut watcher, &path);
+    watcher.watch().expect("Couldn't start watching");
+
+    latency(&mut watcher, &path);
+    idle_wakeups(&mut watcher);
+    registration(&directory);
+    debouncing();
+
+    let _ = fs::remove_dir_all(&directory);
+}
+
+
+/// Time from appending a line to the main loop taking the file as due for a
+/// read: the kqueue event, plus the debounce window.
+fn latency(watcher: &mut KqueueWatcher, path: &Path) {
+    let key = path.to_string_lossy().to_string();
+    let mut debouncer = Debouncer::new(Duration::from_millis(DEBOUNCE_MS));
+    let mut samples = Vec::with_capacity(LATENCY_SAMPLES);
+    for sample in 0..LATENCY_SAMPLES {
+        // a writer thread appends while the loop is parked in poll()
+        let writer_path = path.to_path_buf();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            append(&writer_path, sample);
+            Instant::now()
+        });
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let due = loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break None;
+            }
+            let max_wait = Duration::from_millis(HOUSEKEEPING_INTERVAL_MS);
+            let mut timeout = Some(debouncer.timeout(now, max_wait));
+            while let Some(event) = watcher.poll(timeout) {
+                timeout = None;
+                if let Ident::Filename(_, file_name) = event.ident {
+                    watcher.touch(&file_name);
+                    debouncer.add(&file_name, Instant::now());
+                }
+            }
+            if debouncer.take_due(Instant::now()).contains(&key) {
+                break Some(Instant::now());
+            }
+        };
+        let written = writer.join().expect("Writer thread failed");
+        if let Some(due) = due {
+            samples.push(due.saturating_duration_since(written));
+        }
+    }
+    report(
+        &format!("latency, append to read ({DEBOUNCE_MS} ms debounce window)"),
+        &mut samples,
+    );
+}
+
+
+/// Wake-ups per second of the main loop over a quiet file, which blocks up to
+/// the housekeeping interval.
+fn idle_wakeups(watcher: &mut KqueueWatcher) {
+    let mut debouncer = Debouncer::new(Duration::from_millis(DEBOUNCE_MS));
+    let max_wait = Duration::from_millis(HOUSEKEEPING_INTERVAL_MS);
+    let start = Instant::now();
+    let mut wakeups = 0;
+    while start.elapsed() < IDLE_PERIOD {
+        watcher.watch().expect("Couldn't submit the watch list");
+        let mut timeout = Some(debouncer.timeout(Instant::now(), max_wait));
+        while let Some(event) = watcher.poll(timeout) {
+            timeout = None;
+            if let Ident::Filename(_, file_name) = event.ident {
+                debouncer.add(&file_name, Instant::now());
+            }
+        }
+        debouncer.take_due(Instant::now());
+        wakeups += 1;
+    }
+    println!(
+        "idle wake-ups/s: {:.1}",
+        wakeups as f64 / IDLE_PERIOD.as_secs_f64()
+    );
+}
+
+
+/// Cost of registering a directory's worth of new files, and of the re-walk
+/// registering all of them again, which should cost next to nothing.
+fn registration(directory: &Path) {
+    let paths: Vec<PathBuf> = (0..REGISTERED_FILES)
+        .map(|index| {
+            let path = directory.join(format!("{index}.log"));
+            fs::write(&path, "").expect("Couldn't create benchmark file");
+            path
+        })
+        .collect();
+    let mut watcher = KqueueWatcher::new(0).expect("Could not create kqueue watcher!");
+    for walk in ["first walk", "re-walk, nothing new"] {
+        let start = Instant::now();
+        paths.iter().for_each(|path| register(&mut watcher, path));
+        watcher.watch().expect("Couldn't submit the watch list");
+        let elapsed = start.elapsed();
+        println!(
+            "registration, {walk}: {REGISTERED_FILES} files in {elapsed:?}, {:.1} µs/file",
+            elapsed.as_secs_f64() * 1e6 / REGISTERED_FILES as f64
+        );
+    }
+}
+
+
+/// CPU cost of coalescing a flood of events, and how many reads it leaves.
+fn debouncing() {
+    let paths: Vec<String> = (0..DEBOUNCED_PATHS)
+        .map(|index| format!("/var/log/app/{index}.log"))
+        .collect();
+    let window = Duration::from_millis(DEBOUNCE_MS);
+    let mut debouncer = Debouncer::new(window);
+    let start = Instant::now();
+    let mut reads = 0;
+    for (index, path) in paths.iter().cycle().take(DEBOUNCED_EVENTS).enumerate() {
+        // events spread evenly over one second of simulated time
+        let at = start + Duration::from_micros(index as u64);
+        debouncer.add(path, at);
+        if index % DEBOUNCED_PATHS == 0 {
+            reads += debouncer.take_due(at).len();
+        }
+    }
+    reads += debouncer.take_due(start + Duration::from_secs(2)).len();
+    let elapsed = start.elapsed();
+    println!(
+        "debouncing: {DEBOUNCED_EVENTS} events -> {reads} reads, {:.1} ns/event",
+        elapsed.as_nanos() as f64 / DEBOUNCED_EVENTS as f64
+    );
+}
+
+
+/// Register the benchmark file at `path` with the watcher.
+fn register(watcher: &mut KqueueWatcher, path: &Path) {
+    let inode = fs::metadata(path)
+        .expect("Couldn't read benchmark file")
+        .ino();
+    watcher
+        .register(path, inode, true)
+        .expect("Couldn't watch benchmark file");
+}
+
+
+/// Append one line to the benchmark file.
+fn append(path: &Path, sample: usize) {
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "sample {sample}"))
+        .expect("Couldn't append to benchmark file");
+}
+
+
+/// Print median and worst of the collected samples.
+fn report(name: &str, samples: &mut [Duration]) {
+    if samples.is_empty() {
+        println!("{name}: no events delivered");
+        return;
+    }
+    samples.sort();
+    println!(
+        "{name}: median {:?}, max {:?} ({} samples)",
+        samples[samples.len() / 2],
+        samples[samples.len() - 1],
+        samples.len()
+    );
+}
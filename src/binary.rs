@@ -0,0 +1,266 @@
+//! Binary content sniffing.
+//!
+//! Database files and core dumps showing up in log directories change all the
+//! time, and reading their appended bytes as lines only produces garbage. A
+//! file is sniffed once, when first seen: NUL bytes, or a high ratio of bytes
+//! that aren't valid UTF-8 in its first block, mark it binary.
+//!
+//! For binary-ish formats whose appends are worth seeing, the new byte range
+//! can be rendered as a classic offset/hex/ASCII dump instead.
+
+use crate::consts::{BINARY_INVALID_UTF8_PERCENT, BINARY_SNIFF_BYTES};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    str::FromStr,
+};
+
+
+/// Bytes shown per hexdump line.
+const HEXDUMP_LINE_BYTES: usize = 16;
+
+
+/// What to do on changes of files sniffed as binary. Configured by its name,
+/// as a string.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum BinaryFiles {
+    /// Print how much they grew.
+    #[default]
+    Summary,
+
+    /// Suppress them.
+    Skip,
+}
+
+
+impl FromStr for BinaryFiles {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "summary" => Ok(BinaryFiles::Summary),
+            "skip" => Ok(BinaryFiles::Skip),
+            other => {
+                Err(format!(
+                    "Unknown binary_files mode: {other} (expected: summary or skip)"
+                ))
+            }
+        }
+    }
+}
+
+
+impl TryFrom<String> for BinaryFiles {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+
+impl From<BinaryFiles> for String {
+    fn from(mode: BinaryFiles) -> Self {
+        mode.to_string()
+    }
+}
+
+
+impl fmt::Display for BinaryFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryFiles::Summary => write!(f, "summary"),
+            BinaryFiles::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+
+/// Whether `block`, the start of some content, looks binary.
+pub fn sniff(block: &[u8]) -> bool {
+    if block.contains(&0) {
+        return true;
+    }
+    let chunks: Vec<_> = block.utf8_chunks().collect();
+    let invalid: usize = chunks
+        .iter()
+        .enumerate()
+        // a multi-byte character cut off at the end of the block is fine
+        .map(|(index, chunk)| {
+            if index + 1 == chunks.len() && chunk.invalid().len() < 4 {
+                0
+            } else {
+                chunk.invalid().len()
+            }
+        })
+        .sum();
+    invalid * 100 > block.len() * BINARY_INVALID_UTF8_PERCENT
+}
+
+
+/// Whether the file at `path` looks binary, judging by its first block. Files
+/// that can't be read (or are empty) are not.
+pub fn is_binary_file(path: &Path) -> bool {
+    let mut block = Vec::with_capacity(BINARY_SNIFF_BYTES);
+    File::open(path)
+        .and_then(|file| file.take(BINARY_SNIFF_BYTES as u64).read_to_end(&mut block))
+        .map(|_| sniff(&block))
+        .unwrap_or(false)
+}
+
+
+/// Render `bytes`, found at `offset` of their file, as hexdump lines:
+/// `00000010  68 65 6c 6c 6f 0a 00 ff  ...  |hello...|`.
+pub fn hexdump(bytes: &[u8], offset: u64) -> Vec<String> {
+    bytes
+        .chunks(HEXDUMP_LINE_BYTES)
+        .enumerate()
+        .map(|(index, line)| {
+            let hex: Vec<String> = (0..HEXDUMP_LINE_BYTES)
+                .map(|column| {
+                    line.get(column)
+                        .map_or_else(|| "  ".to_string(), |byte| format!("{byte:02x}"))
+                })
+                .collect();
+            let ascii: String = line
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        char::from(byte)
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08x}  {}  {}  |{}|",
+                offset + (index * HEXDUMP_LINE_BYTES) as u64,
+                hex[..HEXDUMP_LINE_BYTES / 2].join(" "),
+                hex[HEXDUMP_LINE_BYTES / 2..].join(" "),
+                ascii
+            )
+        })
+        .collect()
+}
+
+
+/// Hexdump of the bytes of the file at `path` from `position` up to
+/// `file_size`, capped at `max_bytes` (`0` for no cap). A capped dump ends with
+/// a line telling how many bytes were left out.
+pub fn read_hexdump(
+    path: &Path,
+    position: u64,
+    file_size: u64,
+    max_bytes: usize,
+) -> io::Result<Vec<String>> {
+    let length = file_size.saturating_sub(position);
+    let shown = match max_bytes {
+        0 => length,
+        max_bytes => length.min(max_bytes as u64),
+    };
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(position))?;
+    let mut bytes = Vec::new();
+    file.take(shown).read_to_end(&mut bytes)?;
+    let mut lines = hexdump(&bytes, position);
+    let left_out = length - bytes.len() as u64;
+    if left_out > 0 {
+        lines.push(format!("* {} more *", format_size(left_out)));
+    }
+    Ok(lines)
+}
+
+
+/// Human readable size: `512 B`, `4 KB`, `1.5 MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    let size = format!("{size:.1}");
+    format!(
+        "{} {}",
+        size.strip_suffix(".0").unwrap_or(&size),
+        UNITS[unit]
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{format_size, hexdump, is_binary_file, read_hexdump, sniff};
+    use std::fs;
+
+    #[test]
+    fn nul_bytes_mean_binary() {
+        assert!(sniff(b"SQLite format 3\0\x10\x00"));
+        assert!(!sniff(b"plain log line\n"));
+        assert!(!sniff(b""));
+    }
+
+    #[test]
+    fn mostly_invalid_utf8_is_binary() {
+        assert!(sniff(&[0xff, 0xfe, 0x80, 0x81, b'a', 0x90, 0xc3]));
+        assert!(!sniff("zażółć gęślą jaźń\n".as_bytes()));
+        // a stray invalid byte in a long text doesn't make it binary
+        let mut text = b"a mostly valid log line\n".repeat(10);
+        text.push(0xff);
+        assert!(!sniff(&text));
+    }
+
+    #[test]
+    fn character_cut_at_block_end_is_not_invalid() {
+        let text = "łłłł".as_bytes();
+        assert!(!sniff(&text[..text.len() - 1]));
+    }
+
+    #[test]
+    fn files_are_sniffed_by_their_first_block() {
+        let path = std::env::temp_dir().join(format!("lw_binary_test_{}", std::process::id()));
+        fs::write(&path, b"\x7fELF\x02\x01\x01\0\0\0").unwrap();
+        assert!(is_binary_file(&path));
+        fs::write(&path, "text\n").unwrap();
+        assert!(!is_binary_file(&path));
+        let _ = fs::remove_file(&path);
+        assert!(!is_binary_file(&path), "missing files are not binary");
+    }
+
+    #[test]
+    fn hexdump_shows_offsets_hex_and_ascii() {
+        assert_eq!(
+            hexdump(b"hello\n\0\xffworld, binary!", 16),
+            vec![
+                "00000010  68 65 6c 6c 6f 0a 00 ff  77 6f 72 6c 64 2c 20 62  |hello...world, b|",
+                "00000020  69 6e 61 72 79 21                                 |inary!|",
+            ]
+        );
+        assert!(hexdump(b"", 0).is_empty());
+    }
+
+    #[test]
+    fn hexdump_of_a_range_is_capped() {
+        let path =
+            std::env::temp_dir().join(format!("lw_hexdump_test_{}", std::process::id()));
+        fs::write(&path, [7u8; 100]).unwrap();
+        let lines = read_hexdump(&path, 50, 100, 20).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00000032  07 07"));
+        assert_eq!(lines[2], "* 30 B more *");
+        assert_eq!(read_hexdump(&path, 96, 100, 0).unwrap().len(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(4096), "4 KB");
+        assert_eq!(format_size(1536 * 1024), "1.5 MB");
+    }
+}
@@ -0,0 +1,424 @@
+//! Command line parsing.
+//!
+//! `lw` takes a handful of flags followed by the paths to watch, so a small
+//! hand-rolled parser is all it needs.
+
+use crate::config::Config;
+use std::str::FromStr;
+
+
+/// Usage text printed for `--help` and on invalid arguments.
+pub const USAGE: &str = "\
+Usage: lw [OPTIONS] <PATH>... [-- <COMMAND> [ARGS]...]
+       lw config <show [--origin] [OPTIONS]|default|check [CONFIG]|init [CONFIG]>
+
+Paths are followed until interrupted. A path of `-` reads stdin; named pipes
+and character devices are read as streams too. With a COMMAND, it is run and its
+output shown next to the paths; lw then exits with the command's exit code.
+
+Configuration files are layered: /etc/lw.conf, the user's ~/.lw.conf and the
+project's ./lw.conf (see README), each overriding the fields it sets.
+Without any lw runs with built-in defaults. `lw config show` prints the
+configuration in effect with the given --profile and options applied, unset
+values resolved to what lw uses (with `--origin`, where each value came from),
+`lw config default` the defaults, and `lw config init` writes a commented
+template (to CONFIG, or ~/.lw.conf). `lw config check` strictly checks the
+configuration files (CONFIG, or the ones lw would use), reporting every
+problem found; it exits non-zero if any.
+
+Options:
+  --once, --no-follow   Print the tail of every discovered file and exit
+  --sort <path|mtime>   Order of printed tails (default: path)
+  -n, --lines <N>       Lines of tail to print per file
+  --initial-tails       Print tails of existing files before following them
+  --initial-files <K>   Print startup tails of only the K most recently
+                        modified files (0 prints all)
+  --include <GLOB>      Watch only files matching GLOB (repeatable); ignore
+                        patterns still take precedence
+  --ignore-files        Respect .gitignore, .ignore and .lwignore files
+  --profile <NAME>      Apply the configuration profile NAME, watching its
+                        paths when none are given
+  --pid <PID>           Exit once this process exits (repeatable)
+  -h, --help            Print this help";
+
+
+/// Order in which tails of discovered files are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TailOrder {
+    /// Alphabetically by path.
+    #[default]
+    Path,
+
+    /// By modification time, most recently modified last.
+    Mtime,
+}
+
+
+impl FromStr for TailOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "path" => Ok(TailOrder::Path),
+            "mtime" => Ok(TailOrder::Mtime),
+            other => {
+                Err(format!(
+                    "Unknown sort order: {other} (expected: path or mtime)"
+                ))
+            }
+        }
+    }
+}
+
+
+/// `lw config` subcommands, handled instead of watching anything.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigCommand {
+    /// Print the configuration in effect, with the origin of every value
+    /// when `origins` is set.
+    Show {
+        /// Print where each value came from.
+        origins: bool,
+    },
+
+    /// Print the built-in default configuration.
+    Default,
+
+    /// Strictly check the configuration file at the given path, or the one in
+    /// use.
+    Check(Option<String>),
+
+    /// Write a commented configuration template to the given path, or the
+    /// default one.
+    Init(Option<String>),
+}
+
+
+impl ConfigCommand {
+    /// Parse the `subcommand` following `config`, with its `arguments`.
+    /// `None` when it isn't one, so `lw config` can still watch a path
+    /// named `config`.
+    fn parse(subcommand: &str, arguments: &[String]) -> Result<Option<Self>, String> {
+        match (subcommand, arguments) {
+            ("show", _) => {
+                Ok(Some(ConfigCommand::Show {
+                    origins: arguments.iter().any(|argument| argument == "--origin"),
+                }))
+            }
+            ("default", []) => Ok(Some(ConfigCommand::Default)),
+            ("check", [] | [_]) => Ok(Some(ConfigCommand::Check(arguments.first().cloned()))),
+            ("init", [] | [_]) => Ok(Some(ConfigCommand::Init(arguments.first().cloned()))),
+            ("default" | "check" | "init", _) => {
+                Err(format!("Too many arguments for: config {subcommand}"))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+
+/// Parsed command line arguments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cli {
+    /// Files and directories to watch.
+    pub paths: Vec<String>,
+
+    /// Print the current tails and exit instead of following.
+    pub once: bool,
+
+    /// Order of the printed tails.
+    pub sort: TailOrder,
+
+    /// Lines of tail to print per file, overriding the configuration.
+    pub tail_lines: Option<usize>,
+
+    /// Print tails of existing files at startup.
+    pub initial_tails: bool,
+
+    /// Cap on files whose startup tails are printed, overriding the
+    /// configuration.
+    pub initial_tail_files: Option<usize>,
+
+    /// Respect ignore files in watched directories.
+    pub ignore_files: bool,
+
+    /// Glob patterns of the files to watch, overriding the configured ones.
+    pub includes: Vec<String>,
+
+    /// Name of the configuration profile to apply.
+    pub profile: Option<String>,
+
+    /// Processes to outlive: `lw` exits after all of them have exited.
+    pub pids: Vec<i32>,
+
+    /// Command (program and its arguments) to run, showing its output.
+    pub command: Vec<String>,
+
+    /// Print usage and exit.
+    pub help: bool,
+
+    /// `lw config` subcommand to run instead of watching.
+    pub config_command: Option<ConfigCommand>,
+}
+
+
+impl Cli {
+    /// Parse arguments (without the program name).
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+        let mut cli = Cli::default();
+        let args: Vec<String> = args.into_iter().collect();
+        if let [command, subcommand, arguments @ ..] = args.as_slice()
+            && command == "config"
+            && let Some(config_command) = ConfigCommand::parse(subcommand, arguments)?
+        {
+            // `config show` takes the options changing the configuration it shows
+            if let ConfigCommand::Show {
+                ..
+            } = config_command
+            {
+                cli = Cli::parse(
+                    arguments
+                        .iter()
+                        .filter(|argument| *argument != "--origin")
+                        .cloned()
+                        .collect::<Vec<_>>(),
+                )?;
+                if !cli.paths.is_empty() || !cli.command.is_empty() {
+                    return Err(format!("Too many arguments for: config {subcommand}"));
+                }
+            }
+            cli.config_command = Some(config_command);
+            return Ok(cli);
+        }
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--once" | "--no-follow" => cli.once = true,
+                "--sort" => cli.sort = Self::value_of(&arg, args.next())?.parse()?,
+                "-n" | "--lines" => cli.tail_lines = Some(Self::number_of(&arg, args.next())?),
+                "--initial-tails" => cli.initial_tails = true,
+                "--initial-files" => {
+                    cli.initial_tail_files = Some(Self::number_of(&arg, args.next())?)
+                }
+                "--ignore-files" => cli.ignore_files = true,
+                "--include" => cli.includes.push(Self::value_of(&arg, args.next())?),
+                "--profile" => cli.profile = Some(Self::value_of(&arg, args.next())?),
+                "--pid" => {
+                    let value = Self::value_of(&arg, args.next())?;
+                    cli.pids.push(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid process id: {value}"))?,
+                    )
+                }
+                "-h" | "--help" => cli.help = true,
+                "--" => cli.command.extend(args.by_ref()),
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("Unknown option: {flag}"));
+                }
+                _ => cli.paths.push(arg),
+            }
+        }
+        Ok(cli)
+    }
+
+
+    /// Apply the options that override configuration values, recording them
+    /// as their origin.
+    pub fn override_config(&self, config: &mut Config) {
+        if let Some(tail_lines) = self.tail_lines {
+            config.tail_lines = Some(tail_lines);
+            config.set_origin("tail_lines", "command line: --lines");
+        }
+        if self.initial_tails {
+            config.initial_tails = Some(true);
+            config.set_origin("initial_tails", "command line: --initial-tails");
+        }
+        if let Some(initial_tail_files) = self.initial_tail_files {
+            config.initial_tail_files = Some(initial_tail_files);
+            config.set_origin("initial_tail_files", "command line: --initial-files");
+        }
+        if self.ignore_files {
+            config.ignore_files = Some(true);
+            config.set_origin("ignore_files", "command line: --ignore-files");
+        }
+        if !self.includes.is_empty() {
+            config.set_include_patterns(self.includes.clone());
+            config.set_origin("include_patterns", "command line: --include");
+        }
+    }
+
+
+    /// The value following `flag`, or an error if it is missing.
+    fn value_of(flag: &str, value: Option<String>) -> Result<String, String> {
+        value.ok_or_else(|| format!("Missing value for option: {flag}"))
+    }
+
+
+    /// The numeric value following `flag`.
+    fn number_of(flag: &str, value: Option<String>) -> Result<usize, String> {
+        let value = Self::value_of(flag, value)?;
+        value
+            .parse()
+            .map_err(|_| format!("Invalid number for option {flag}: {value}"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Cli, ConfigCommand, TailOrder};
+    use crate::config::Config;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn plain_arguments_are_paths() {
+        let cli = parse(&["/var/log", "app.log"]).unwrap();
+        assert_eq!(cli.paths, vec!["/var/log", "app.log"]);
+        assert!(!cli.once);
+        assert_eq!(cli.sort, TailOrder::Path);
+    }
+
+    #[test]
+    fn once_and_its_alias_enable_one_shot_mode() {
+        assert!(parse(&["--once", "/var/log"]).unwrap().once);
+        assert!(parse(&["/var/log", "--no-follow"]).unwrap().once);
+    }
+
+    #[test]
+    fn sort_takes_a_value() {
+        let cli = parse(&["--once", "--sort", "mtime", "/var/log"]).unwrap();
+        assert_eq!(cli.sort, TailOrder::Mtime);
+        assert_eq!(cli.paths, vec!["/var/log"]);
+        assert!(parse(&["--sort", "size"]).is_err());
+        assert!(parse(&["--sort"]).is_err());
+    }
+
+    #[test]
+    fn tail_options_override_config() {
+        let cli = parse(&[
+            "-n",
+            "25",
+            "--initial-tails",
+            "--initial-files",
+            "3",
+            "--ignore-files",
+            ".",
+        ])
+        .unwrap();
+        let mut config = Config::default();
+        cli.override_config(&mut config);
+        assert_eq!(config.ignore_files, Some(true));
+        assert_eq!(config.tail_lines, Some(25));
+        assert_eq!(config.initial_tails, Some(true));
+        assert_eq!(config.initial_tail_files, Some(3));
+        assert_eq!(config.origin("tail_lines"), "command line: --lines");
+        assert!(parse(&["--lines", "many"]).is_err());
+    }
+
+    #[test]
+    fn absent_options_keep_config_values() {
+        let mut config = Config::default();
+        parse(&["."]).unwrap().override_config(&mut config);
+        assert_eq!(config.tail_lines, Config::default().tail_lines);
+        assert_eq!(config.initial_tails, Some(false));
+        assert_eq!(config.ignore_files, Some(false));
+        assert_eq!(config.origin("tail_lines"), "default");
+    }
+
+    #[test]
+    fn includes_replace_configured_patterns() {
+        let cli = parse(&["--include", "*.log", "--include", "*.err", "/var/log"]).unwrap();
+        assert_eq!(cli.includes, vec!["*.log", "*.err"]);
+        let mut config = Config::default();
+        cli.override_config(&mut config);
+        assert_eq!(
+            config.include_patterns,
+            Some(vec!["*.log".to_string(), "*.err".to_string()])
+        );
+        assert_eq!(config.include_globs().len(), 2);
+        assert!(parse(&["--include"]).is_err());
+    }
+
+    #[test]
+    fn profile_takes_a_name() {
+        assert_eq!(
+            parse(&["--profile", "web"]).unwrap().profile.as_deref(),
+            Some("web")
+        );
+        assert!(parse(&["--profile"]).is_err());
+    }
+
+    #[test]
+    fn pid_is_repeatable() {
+        let cli = parse(&["--pid", "42", "logs", "--pid", "7"]).unwrap();
+        assert_eq!(cli.pids, vec![42, 7]);
+        assert_eq!(cli.paths, vec!["logs"]);
+        assert!(parse(&["--pid", "self"]).is_err());
+    }
+
+    #[test]
+    fn everything_after_double_dash_is_the_command() {
+        let cli = parse(&["logs", "--", "make", "deploy", "--pid", "1"]).unwrap();
+        assert_eq!(cli.paths, vec!["logs"]);
+        assert_eq!(cli.command, vec!["make", "deploy", "--pid", "1"]);
+        assert!(cli.pids.is_empty());
+    }
+
+    #[test]
+    fn config_subcommands() {
+        assert_eq!(
+            parse(&["config", "show"]).unwrap().config_command,
+            Some(ConfigCommand::Show {
+                origins: false
+            })
+        );
+        assert_eq!(
+            parse(&["config", "show", "--origin"])
+                .unwrap()
+                .config_command,
+            Some(ConfigCommand::Show {
+                origins: true
+            })
+        );
+        assert_eq!(
+            parse(&["config", "init"]).unwrap().config_command,
+            Some(ConfigCommand::Init(None))
+        );
+        assert!(parse(&["config", "default", "x"]).is_err());
+        let cli =
+            parse(&["config", "show", "--profile", "web", "--origin", "-n", "5"]).unwrap();
+        assert_eq!(
+            cli.config_command,
+            Some(ConfigCommand::Show {
+                origins: true
+            })
+        );
+        assert_eq!(cli.profile.as_deref(), Some("web"));
+        assert_eq!(cli.tail_lines, Some(5));
+        assert!(parse(&["config", "show", "/var/log"]).is_err());
+        assert_eq!(
+            parse(&["config", "check"]).unwrap().config_command,
+            Some(ConfigCommand::Check(None))
+        );
+        assert_eq!(
+            parse(&["config", "check", "lw.conf"])
+                .unwrap()
+                .config_command,
+            Some(ConfigCommand::Check(Some("lw.conf".to_string())))
+        );
+        assert!(parse(&["config", "check", "a", "b"]).is_err());
+        // a directory called `config` is still a path to watch
+        let cli = parse(&["config", "logs"]).unwrap();
+        assert_eq!(cli.paths, vec!["config", "logs"]);
+        assert!(cli.config_command.is_none());
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert!(parse(&["--bogus", "/var/log"]).is_err());
+    }
+}
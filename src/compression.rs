@@ -0,0 +1,251 @@
+//! Transparent decompression of rotated logs (gzip, zstd, bzip2, xz).
+//!
+//! Compressed files are recognised by their magic bytes rather than by their
+//! extension, so `app.log.1` produced by a misconfigured `logrotate` is read
+//! just as well as `app.log.1.gz`. Decompression is streamed: only the last
+//! `tail_bytes` of decompressed data are ever held in memory.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+
+/// Size of a single decompressed chunk pulled from the decoder at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Longest magic number we sniff for (xz).
+const MAGIC_LEN: usize = 6;
+
+
+/// Compression formats recognised by [`Compression::sniff`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip (`1f 8b`), also covers concatenated multi-member files.
+    Gzip,
+
+    /// Zstandard (`28 b5 2f fd`).
+    Zstd,
+
+    /// bzip2 (`BZh`).
+    Bzip2,
+
+    /// xz (`fd 37 7a 58 5a 00`).
+    Xz,
+}
+
+
+impl Compression {
+    /// Recognise a compression format from the first bytes of a file.
+    pub fn sniff(header: &[u8]) -> Option<Compression> {
+        match header {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [b'B', b'Z', b'h', ..] => Some(Compression::Bzip2),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+
+    /// Sniff the compression format of the file at `path`. Unreadable and
+    /// uncompressed files both yield `None`.
+    pub fn detect(path: &Path) -> Option<Compression> {
+        let mut header = [0u8; MAGIC_LEN];
+        let mut file = File::open(path).ok()?;
+        let mut filled = 0;
+        while filled < MAGIC_LEN {
+            match file.read(&mut header[filled..]) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => filled += read,
+            }
+        }
+        Compression::sniff(&header[..filled])
+    }
+
+
+    /// Wrap `reader` in a streaming decoder for this format.
+    pub fn decoder<'a>(self, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            Compression::Xz => Box::new(liblzma::read::XzDecoder::new_multi_decoder(reader)),
+        })
+    }
+}
+
+
+/// Stream `reader` to its end, keeping only the last `tail_bytes` bytes.
+/// Memory use is bounded by `tail_bytes` plus one read chunk, however large
+/// the decompressed stream is.
+pub fn read_tail(mut reader: impl Read, tail_bytes: u64) -> io::Result<Vec<u8>> {
+    let limit = usize::try_from(tail_bytes).unwrap_or(usize::MAX);
+    let mut tail = VecDeque::with_capacity(limit.min(CHUNK_SIZE));
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        tail.extend(&chunk[..read]);
+        if tail.len() > limit {
+            tail.drain(..tail.len() - limit);
+        }
+    }
+    Ok(tail.into())
+}
+
+
+/// Decompress the file at `path` and return the last `tail_bytes` of its
+/// decompressed content.
+pub fn decompressed_tail(
+    path: &Path,
+    compression: Compression,
+    tail_bytes: u64,
+) -> io::Result<Vec<u8>> {
+    let file = File::open(path)?;
+    read_tail(compression.decoder(file)?, tail_bytes)
+}
+
+
+/// Stream `reader` to its end, keeping only its last `tail_lines` lines.
+/// Invalid UTF-8 is replaced rather than cutting the stream short.
+pub fn read_last_lines(reader: impl Read, tail_lines: usize) -> io::Result<Vec<String>> {
+    if tail_lines == 0 {
+        return Ok(vec![]);
+    }
+    let mut lines = VecDeque::new();
+    for line in BufReader::new(reader).split(b'\n') {
+        if lines.len() == tail_lines {
+            lines.pop_front();
+        }
+        lines.push_back(String::from_utf8_lossy(&line?).into_owned());
+    }
+    Ok(lines.into())
+}
+
+
+/// Decompress the file at `path` and return the last `tail_lines` lines of its
+/// decompressed content.
+pub fn decompressed_last_lines(
+    path: &Path,
+    compression: Compression,
+    tail_lines: usize,
+) -> io::Result<Vec<String>> {
+    let file = File::open(path)?;
+    read_last_lines(compression.decoder(file)?, tail_lines)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Compression, decompressed_tail, read_last_lines, read_tail};
+    use std::{fs, io::Write, path::Path};
+
+    fn temp_path(name: &str) -> String {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "lw_compression_test_{}_{}",
+            std::process::id(),
+            name
+        ));
+        path.to_string_lossy().to_string()
+    }
+
+    fn sample_log(lines: usize) -> String {
+        (0..lines)
+            .map(|n| format!("log line number {n}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn sniffs_known_magic_bytes() {
+        assert_eq!(
+            Compression::sniff(&[0x1f, 0x8b, 0x08]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::sniff(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::sniff(b"BZh91AY"), Some(Compression::Bzip2));
+        assert_eq!(
+            Compression::sniff(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+            Some(Compression::Xz)
+        );
+    }
+
+    #[test]
+    fn plain_text_and_short_headers_are_not_compressed() {
+        assert_eq!(Compression::sniff(b"2024-01-01 INFO started"), None);
+        assert_eq!(Compression::sniff(&[0x1f]), None);
+        assert_eq!(Compression::sniff(&[]), None);
+    }
+
+    #[test]
+    fn read_tail_keeps_only_the_last_bytes() {
+        let data = sample_log(10_000);
+        let tail = read_tail(data.as_bytes(), 64).unwrap();
+        assert_eq!(tail.len(), 64);
+        assert!(data.as_bytes().ends_with(&tail));
+    }
+
+    #[test]
+    fn read_tail_of_short_stream_returns_everything() {
+        assert_eq!(read_tail(&b"short\n"[..], 1024).unwrap(), b"short\n");
+    }
+
+    #[test]
+    fn read_last_lines_keeps_only_the_last_lines() {
+        let data = sample_log(1_000);
+        assert_eq!(
+            read_last_lines(data.as_bytes(), 2).unwrap(),
+            vec!["log line number 998", "log line number 999"]
+        );
+        assert!(read_last_lines(data.as_bytes(), 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn gzip_file_is_detected_and_tail_decompressed() {
+        let path = temp_path("tail.gz");
+        let data = sample_log(5_000);
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data.as_bytes()).unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let compression = Compression::detect(Path::new(&path));
+        assert_eq!(compression, Some(Compression::Gzip));
+        let tail = decompressed_tail(Path::new(&path), Compression::Gzip, 22).unwrap();
+        assert_eq!(tail, b"\nlog line number 4999\n");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn zstd_file_is_detected_and_tail_decompressed() {
+        let path = temp_path("tail.zst");
+        let data = sample_log(100);
+        fs::write(&path, zstd::encode_all(data.as_bytes(), 3).unwrap()).unwrap();
+
+        assert_eq!(
+            Compression::detect(Path::new(&path)),
+            Some(Compression::Zstd)
+        );
+        let tail = decompressed_tail(Path::new(&path), Compression::Zstd, 1024).unwrap();
+        assert!(tail.ends_with(b"log line number 99\n"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn uncompressed_file_is_not_detected() {
+        let path = temp_path("plain.log");
+        fs::write(&path, sample_log(3)).unwrap();
+        assert_eq!(Compression::detect(Path::new(&path)), None);
+        let _ = fs::remove_file(&path);
+    }
+}
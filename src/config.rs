@@ -1,18 +1,27 @@
 //! Configuration model and loading (RON-backed, with sane defaults).
 
+use crate::binary::BinaryFiles;
 use crate::consts::{
-    DEFAULT_IGNORE_PATTERNS, DEFAULT_THEME, MAX_DIR_DEPTH, MAX_OPEN_FILES, STDOUT_DEV,
-    TAIL_BYTES,
+    DEBOUNCE_MS, DEFAULT_IGNORE_PATTERNS, DEFAULT_THEME, HEXDUMP_BYTES, INITIAL_TAIL_FILES,
+    MAX_DIR_DEPTH, MAX_OPEN_FILES, STDOUT_DEV, TAIL_BYTES, TAIL_LINES,
 };
-use crate::utils::write_append;
+use crate::encoding::for_label;
+use crate::glob::{self, Glob, SplitPath, matches_any, split_static_prefix};
+use crate::highlight;
+use crate::ignore_files::IgnoreFiles;
+use crate::rules::{FileRules, Rule};
 use std::{
-    env,
-    fs::read_to_string,
-    io::{Error, ErrorKind},
-    path::Path,
+    cmp::Reverse,
+    collections::BTreeMap,
+    env, fmt,
+    fs::{OpenOptions, read_to_string},
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use colored::Colorize;
+use encoding_rs::Encoding;
 use log::LevelFilter;
 use ron::ser::{PrettyConfig, to_string_pretty};
 use serde::{Deserialize, Serialize};
@@ -27,33 +36,194 @@ pub struct Config {
     /// Log level
     pub log_level: Option<String>,
 
-    /// Max amount of open files by watcher
+    /// Max amount of open files by watcher. Once reached, the least recently
+    /// active files are polled instead of watched; `0` means no limit.
     pub max_open_files: Option<usize>,
 
     /// How many bytes of tail to show fornewly watched files
     pub tail_bytes: Option<u64>,
 
+    /// How many lines of tail to show for existing files at startup and in
+    /// one-shot mode.
+    #[serde(default = "default_tail_lines")]
+    pub tail_lines: Option<usize>,
+
+    /// Show tails of existing files at startup, before following them?
+    #[serde(default = "default_initial_tails")]
+    pub initial_tails: Option<bool>,
+
+    /// Show startup tails of only this many most recently modified files
+    /// (0 shows all of them).
+    #[serde(default = "default_initial_tail_files")]
+    pub initial_tail_files: Option<usize>,
+
     /// Follow symlinks?
     pub follow_links: Option<bool>,
 
     /// How deep to go in directory tree
     pub max_dir_depth: Option<usize>,
 
-    /// Filename glob patterns to ignore (transient temp/swap/backup files).
+    /// Events for the same path arriving within this many milliseconds are
+    /// coalesced, so a busy file is read once per window.
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: Option<u64>,
+
+    /// What to do on changes of files sniffed as binary: "summary" prints how
+    /// much they grew, "skip" suppresses them.
+    #[serde(default = "default_binary_files")]
+    pub binary_files: Option<BinaryFiles>,
+
+    /// Glob patterns of files whose changes are shown as a hexdump of the new
+    /// bytes (for binary-ish formats worth seeing), instead of as lines or a
+    /// binary summary.
+    #[serde(default)]
+    pub hexdump_patterns: Option<Vec<String>>,
+
+    /// Most bytes shown in the hexdump of a single change (0 shows all).
+    #[serde(default = "default_hexdump_bytes")]
+    pub hexdump_bytes: Option<usize>,
+
+    /// Text encodings of files by glob pattern, as `(pattern, label)` pairs
+    /// (e.g. `("legacy/*.log", "latin1")`, `("*.win.log", "utf-16le")`); the
+    /// first matching pattern wins. A byte order mark overrides them, and
+    /// files matching none are read as UTF-8.
+    #[serde(default)]
+    pub encodings: Option<Vec<(String, String)>>,
+
+    /// Glob patterns to ignore (transient temp/swap/backup files, directories
+    /// like `node_modules/` or `**/cache/**`); see [`crate::glob`] for the
+    /// syntax. Excluded directories are never descended into.
     /// Missing from older config files -> falls back to the built-in defaults.
     #[serde(default = "default_ignore_patterns")]
     pub ignore_patterns: Option<Vec<String>>,
 
+    /// Respect `.gitignore`, `.ignore` and `.lwignore` files found in watched
+    /// directories?
+    #[serde(default = "default_ignore_files")]
+    pub ignore_files: Option<bool>,
+
+    /// Glob patterns of the files to watch (e.g. `*.log`), with the same
+    /// syntax as [`Self::ignore_patterns`]. Unset or empty watches all files;
+    /// directories are always walked, and ignore patterns take precedence.
+    #[serde(default)]
+    pub include_patterns: Option<Vec<String>>,
+
     /// syntect theme name for syntax-highlighted output (e.g.
     /// "base16-ocean.dark", "Solarized (dark)", "InspiredGitHub").
     #[serde(default = "default_theme")]
     pub theme: Option<String>,
 
-    /// [`Self::ignore_patterns`] precompiled to char slices. Derived (never
-    /// serialized): filled once by [`Config::with_compiled_globs`] so filename
+    /// Directories of custom `.sublime-syntax` files, merged into the bundled
+    /// syntaxes (and taking precedence over them).
+    #[serde(default)]
+    pub syntax_dirs: Option<Vec<String>>,
+
+    /// Directories of custom `.tmTheme` files, selectable as [`Self::theme`]
+    /// by their file name without the extension.
+    #[serde(default)]
+    pub theme_dirs: Option<Vec<String>>,
+
+    /// File caching the syntaxes and themes compiled from
+    /// [`Self::syntax_dirs`] and [`Self::theme_dirs`] as a binary dump, for a
+    /// fast startup. It is rebuilt when any of their files change.
+    #[serde(default)]
+    pub syntax_cache: Option<String>,
+
+    /// Syntaxes of files by glob pattern, as `(pattern, syntax)` pairs with the
+    /// syntax given by its name or file extension (e.g. `("Jenkinsfile",
+    /// "Groovy")`, `("nginx/*.conf", "nginx")`); the first matching pattern
+    /// wins. Files matching none are detected by their name and first line.
+    #[serde(default)]
+    pub syntaxes: Option<Vec<(String, String)>>,
+
+    /// Settings overridden per file, for the files matching the glob pattern
+    /// of each rule (see [`Rule`]). All matching rules apply, the later ones
+    /// overriding the earlier ones.
+    #[serde(default)]
+    pub rules: Option<Vec<Rule>>,
+
+    /// List fields (like `ignore_patterns` or `rules`) whose values in this
+    /// file are appended to the ones of the layers below it, instead of
+    /// replacing them.
+    #[serde(default)]
+    pub append: Option<Vec<String>>,
+
+    /// Named profiles, selected with `--profile <name>`: partial
+    /// configurations whose fields override the ones above, plus `paths` to
+    /// watch when none are given as arguments and the name of a profile they
+    /// `inherits` from. Profiles of a later layer replace same-named ones.
+    #[serde(default)]
+    pub profiles: Option<BTreeMap<String, ron::Value>>,
+
+    /// [`Self::ignore_patterns`] precompiled to globs. Derived (never
+    /// serialized): filled once by [`Config::with_compiled_globs`] so path
     /// matching in the event hot path never re-parses the pattern strings.
     #[serde(skip)]
-    ignore_globs: Vec<Vec<char>>,
+    ignore_globs: Vec<Glob>,
+
+    /// [`Self::include_patterns`] precompiled to globs, like `ignore_globs`.
+    #[serde(skip)]
+    include_globs: Vec<Glob>,
+
+    /// [`Self::hexdump_patterns`] precompiled to globs, like `ignore_globs`.
+    #[serde(skip)]
+    hexdump_globs: Vec<Glob>,
+
+    /// [`Self::encodings`] with their patterns precompiled and labels resolved.
+    #[serde(skip)]
+    encoding_globs: Vec<(Glob, &'static Encoding)>,
+
+    /// [`Self::syntaxes`] with their patterns precompiled.
+    #[serde(skip)]
+    syntax_globs: Vec<(Glob, String)>,
+
+    /// [`Self::rules`] with their patterns precompiled.
+    #[serde(skip)]
+    rule_globs: Vec<(Glob, Rule)>,
+
+    /// Watched roots that patterns are anchored to, longest first. Derived from
+    /// the path arguments by [`Config::set_roots`].
+    #[serde(skip)]
+    roots: Vec<Root>,
+
+    /// Layers the fields were last set by, by field name (see
+    /// [`Config::origin`]).
+    #[serde(skip)]
+    origins: BTreeMap<String, String>,
+
+    /// Paths to watch of the selected profile (see [`Config::apply_profile`]).
+    #[serde(skip)]
+    profile_paths: Vec<String>,
+
+    /// Rules of the ignore files read so far, when [`Self::ignore_files`] are
+    /// respected.
+    #[serde(skip)]
+    ignore_file_rules: IgnoreFiles,
+}
+
+
+/// The keys of a profile that aren't configuration fields.
+#[derive(Debug, Default, Deserialize)]
+struct ProfileKeys {
+    /// Paths to watch when none are given as arguments.
+    #[serde(default)]
+    paths: Option<Vec<String>>,
+
+    /// Name of the profile whose fields apply first.
+    #[serde(default)]
+    inherits: Option<String>,
+}
+
+
+/// A watched root directory (or file) given as path argument.
+#[derive(Clone, Debug)]
+struct Root {
+    path: PathBuf,
+
+    /// Patterns of glob arguments with this root as static prefix, selecting
+    /// the files to watch below it. `None` when the root was given as a plain
+    /// path, watching all of it.
+    patterns: Option<Vec<Glob>>,
 }
 
 
@@ -69,6 +239,49 @@ fn default_ignore_patterns() -> Option<Vec<String>> {
 }
 
 
+/// Serde fallback for [`Config::tail_lines`].
+fn default_tail_lines() -> Option<usize> {
+    Some(TAIL_LINES)
+}
+
+
+/// Serde fallback for [`Config::initial_tails`]: off, like before the option
+/// existed.
+fn default_initial_tails() -> Option<bool> {
+    Some(false)
+}
+
+
+/// Serde fallback for [`Config::initial_tail_files`].
+fn default_initial_tail_files() -> Option<usize> {
+    Some(INITIAL_TAIL_FILES)
+}
+
+
+/// Serde fallback for [`Config::ignore_files`]: ignore files are opt-in.
+fn default_ignore_files() -> Option<bool> {
+    Some(false)
+}
+
+
+/// Serde fallback for [`Config::binary_files`].
+fn default_binary_files() -> Option<BinaryFiles> {
+    Some(BinaryFiles::Summary)
+}
+
+
+/// Serde fallback for [`Config::hexdump_bytes`].
+fn default_hexdump_bytes() -> Option<usize> {
+    Some(HEXDUMP_BYTES)
+}
+
+
+/// Serde fallback for [`Config::debounce_ms`].
+fn default_debounce_ms() -> Option<u64> {
+    Some(DEBOUNCE_MS)
+}
+
+
 /// Serde fallback for [`Config::theme`] so config files written before this
 /// option existed still deserialize with the default theme.
 fn default_theme() -> Option<String> {
@@ -76,6 +289,272 @@ fn default_theme() -> Option<String> {
 }
 
 
+/// Comments preceding the fields of the [`Config::template`].
+const FIELD_COMMENTS: &[(&str, &str)] = &[
+    ("output", "Where to print output"),
+    ("log_level", "OFF, ERROR, WARN, INFO, DEBUG or TRACE"),
+    (
+        "max_open_files",
+        "Most files watched at once; less active ones are polled (0: no limit)",
+    ),
+    ("tail_bytes", "Bytes of tail shown for newly watched files"),
+    (
+        "tail_lines",
+        "Lines of tail shown at startup and with --once",
+    ),
+    ("initial_tails", "Show tails of existing files at startup"),
+    (
+        "initial_tail_files",
+        "Show startup tails of only this many most recently modified files (0: all)",
+    ),
+    ("follow_links", "Follow symlinks"),
+    ("max_dir_depth", "How deep to go in directory trees"),
+    (
+        "debounce_ms",
+        "Coalesce events for the same file within this window",
+    ),
+    (
+        "binary_files",
+        "Changes of binary files: \"summary\" or \"skip\"",
+    ),
+    (
+        "hexdump_patterns",
+        "Globs of files whose changes are shown as hexdump",
+    ),
+    ("hexdump_bytes", "Most bytes of a single hexdump (0: all)"),
+    (
+        "encodings",
+        "Encodings of files by glob, e.g. Some([(\"*.log\", \"latin1\")])",
+    ),
+    (
+        "ignore_patterns",
+        "Globs of files and directories to ignore",
+    ),
+    (
+        "ignore_files",
+        "Respect .gitignore, .ignore and .lwignore files",
+    ),
+    (
+        "include_patterns",
+        "Globs of the files to watch (None: all)",
+    ),
+    ("theme", "Syntax highlighting theme"),
+    ("syntax_dirs", "Directories of custom .sublime-syntax files"),
+    (
+        "theme_dirs",
+        "Directories of custom .tmTheme files, selected as theme by file name",
+    ),
+    (
+        "syntax_cache",
+        "File caching the compiled custom syntaxes and themes (None: no cache)",
+    ),
+    (
+        "syntaxes",
+        "Syntaxes of files by glob, e.g. Some([(\"Jenkinsfile\", \"Groovy\")])",
+    ),
+    (
+        "rules",
+        "Settings per glob (show, syntax, encoding, tail_bytes, tail_lines, show_lines, hide_lines, label, color), e.g. Some([(pattern: \"*.err\", color: Some(\"red\"))])",
+    ),
+    (
+        "append",
+        "List fields appended to the ones of the files below this one, e.g. Some([\"ignore_patterns\"])",
+    ),
+    (
+        "profiles",
+        "Profiles for --profile, e.g. Some({\"web\": (paths: Some([\"/var/log/nginx\"]), inherits: Some(\"base\"), tail_lines: Some(50))})",
+    ),
+];
+
+
+/// Keys of a profile that aren't configuration fields.
+const PROFILE_KEYS: [&str; 2] = ["paths", "inherits"];
+
+
+/// Fields holding lists, which a layer may [`Config::append`] to.
+const LIST_FIELDS: [&str; 8] = [
+    "hexdump_patterns",
+    "encodings",
+    "ignore_patterns",
+    "include_patterns",
+    "syntax_dirs",
+    "theme_dirs",
+    "syntaxes",
+    "rules",
+];
+
+
+/// Prefix of the environment variables overriding single fields.
+const ENV_PREFIX: &str = "LW_";
+
+
+/// An `LW_*` environment variable that couldn't be applied, described.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EnvironmentProblem {
+    /// The variable names no configuration field (it may be meant for
+    /// something else), so it's only worth a warning.
+    Unknown(String),
+
+    /// The value of the variable is invalid for its field, which keeps its
+    /// value.
+    Invalid(String),
+}
+
+
+impl fmt::Display for EnvironmentProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvironmentProblem::Unknown(problem) | EnvironmentProblem::Invalid(problem) => {
+                write!(f, "{problem}")
+            }
+        }
+    }
+}
+
+
+/// A problem found by [`Config::load_with_problems`], which left a part of
+/// the configuration out.
+#[derive(Debug)]
+pub enum LoadProblem {
+    /// `$LW_CONFIG` names a file that doesn't exist, so the defaults are used.
+    MissingFile(String),
+
+    /// A configuration file is invalid, so the defaults are used instead of
+    /// any of them.
+    Files(Error),
+
+    /// The profile is unknown or invalid, so the configuration files are used
+    /// without it.
+    Profile(Error),
+
+    /// An `LW_*` environment variable couldn't be applied.
+    Environment(EnvironmentProblem),
+}
+
+
+/// Configuration file paths, lowest layer first, given a lookup of
+/// environment variables: `$LW_CONFIG` alone when set, otherwise the
+/// system-wide ones (`/etc/lw.conf`, `$XDG_CONFIG_DIRS`), the user's
+/// (`$XDG_CONFIG_HOME/lw.conf`, `~/.lw.conf`) and the project's `lw.conf` in
+/// the current directory.
+fn config_paths_in(variable: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    if let Some(config) = variable("LW_CONFIG") {
+        return vec![config];
+    }
+    let home = variable("HOME").unwrap_or_default();
+    let config_home = variable("XDG_CONFIG_HOME").unwrap_or_else(|| format!("{home}/.config"));
+    let config_dirs = variable("XDG_CONFIG_DIRS").unwrap_or_else(|| String::from("/etc/xdg"));
+    let mut paths = vec![String::from("/etc/lw.conf")];
+    // the first of the system-wide directories is the most important one
+    paths.extend(
+        config_dirs
+            .rsplit(':')
+            .filter(|directory| !directory.is_empty())
+            .map(|directory| format!("{directory}/lw.conf")),
+    );
+    paths.extend([
+        format!("{config_home}/lw.conf"),
+        format!("{home}/.lw.conf"),
+        String::from("lw.conf"),
+    ]);
+    paths
+}
+
+
+/// The serialized field value `from` merged over `into`: maps (profiles) are
+/// extended, lists with `append` added to, and anything else replaced.
+fn merge_value(into: Option<ron::Value>, from: ron::Value, append: bool) -> ron::Value {
+    use ron::Value::{Map, Option, Seq};
+    match (into, from) {
+        (Some(Option(Some(into))), Option(Some(from))) => {
+            let merged = match (*into, *from) {
+                (Map(mut entries), Map(more)) => {
+                    for (key, value) in more {
+                        entries.insert(key, value);
+                    }
+                    Map(entries)
+                }
+                (Seq(mut items), Seq(more)) if append => {
+                    items.extend(more);
+                    Seq(items)
+                }
+                (_, from) => from,
+            };
+            Option(Some(Box::new(merged)))
+        }
+        (_, from) => from,
+    }
+}
+
+
+/// Log levels [`Config::get_log_level`] knows.
+const LOG_LEVELS: [&str; 6] = ["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+
+
+/// Fields of the configuration in `file_contents` that [`Config`] doesn't
+/// have (misspelled, or of some other version of `lw`), which deserializing
+/// silently skips.
+fn unknown_fields(file_contents: &str) -> Vec<String> {
+    let known = field_names(&Config::default().to_ron());
+    field_names(file_contents)
+        .into_iter()
+        .filter(|field| !known.contains(field))
+        .collect()
+}
+
+
+/// Names of the fields set in the configuration in `file_contents`.
+fn field_names(file_contents: &str) -> Vec<String> {
+    ron::from_str(file_contents)
+        .map(|value| value_field_names(&value))
+        .unwrap_or_default()
+}
+
+
+/// Names of the fields set in the configuration `value`.
+fn value_field_names(value: &ron::Value) -> Vec<String> {
+    match value {
+        ron::Value::Map(fields) => {
+            fields
+                .keys()
+                .filter_map(|key| {
+                    match key {
+                        ron::Value::String(name) => Some(name.clone()),
+                        _ => None,
+                    }
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+
+/// Read the profile `value`: the configuration fields it sets, their names,
+/// and its profile keys.
+fn read_profile(value: &ron::Value) -> Result<(Config, Vec<String>, ProfileKeys), Error> {
+    let invalid = |err: ron::Error| Error::new(ErrorKind::InvalidInput, err.to_string());
+    let keys: ProfileKeys = value.clone().into_rust().map_err(invalid)?;
+    let layer: Config = value.clone().into_rust().map_err(invalid)?;
+    layer.validate()?;
+    let fields = value_field_names(value)
+        .into_iter()
+        .filter(|field| !PROFILE_KEYS.contains(&field.as_str()))
+        .collect();
+    Ok((layer, fields, keys))
+}
+
+
+/// Compile glob pattern strings (none when unset).
+fn compile_globs(patterns: Option<&[String]>) -> Vec<Glob> {
+    patterns
+        .unwrap_or_default()
+        .iter()
+        .map(|pattern| Glob::new(pattern))
+        .collect()
+}
+
+
 impl Default for Config {
     fn default() -> Self {
         Config {
@@ -83,11 +562,37 @@ impl Default for Config {
             log_level: Some(String::from("INFO")),
             max_open_files: Some(MAX_OPEN_FILES),
             tail_bytes: Some(TAIL_BYTES),
+            tail_lines: default_tail_lines(),
+            initial_tails: default_initial_tails(),
+            initial_tail_files: default_initial_tail_files(),
             max_dir_depth: Some(MAX_DIR_DEPTH),
             follow_links: Some(true),
+            debounce_ms: default_debounce_ms(),
+            binary_files: default_binary_files(),
+            hexdump_patterns: None,
+            hexdump_bytes: default_hexdump_bytes(),
+            encodings: None,
             ignore_patterns: default_ignore_patterns(),
+            ignore_files: default_ignore_files(),
+            include_patterns: None,
             theme: default_theme(),
+            syntax_dirs: None,
+            theme_dirs: None,
+            syntax_cache: None,
+            syntaxes: None,
+            rules: None,
             ignore_globs: Vec::new(),
+            include_globs: Vec::new(),
+            hexdump_globs: Vec::new(),
+            encoding_globs: Vec::new(),
+            syntax_globs: Vec::new(),
+            rule_globs: Vec::new(),
+            append: None,
+            profiles: None,
+            roots: Vec::new(),
+            origins: BTreeMap::new(),
+            profile_paths: Vec::new(),
+            ignore_file_rules: IgnoreFiles::default(),
         }
         .with_compiled_globs()
     }
@@ -95,92 +600,766 @@ impl Default for Config {
 
 
 impl Config {
-    /// Load the `lw` configuration file, falling back to defaults if it is
-    /// missing or invalid.
-    pub fn load() -> Config {
-        let config = Config::get_or_create();
-        read_to_string(&config)
-            .and_then(|file_contents| {
-                ron::from_str::<Config>(&file_contents).map_err(|err| {
-                    let config_error = Error::new(ErrorKind::InvalidInput, err.to_string());
-                    error!(
-                        "Configuration error: {} in file: {}",
-                        err.to_string().red(),
-                        config.cyan()
-                    );
-                    config_error
-                })
-            })
-            .map(Config::with_compiled_globs)
+    /// Load the `lw` configuration (see [`Config::load_with_problems`]). Its
+    /// problems go to stderr, as the logger isn't set up before the
+    /// configuration is known.
+    pub fn load(profile: Option<&str>, overrides: impl FnOnce(&mut Config)) -> Config {
+        let (loaded, problems) = Config::load_with_problems(profile, overrides);
+        for problem in problems {
+            match problem {
+                LoadProblem::MissingFile(path) => {
+                    eprintln!(
+                        "{}: Configuration file: {} doesn't exist (using defaults)",
+                        "ERROR".red(),
+                        path.cyan()
+                    )
+                }
+                LoadProblem::Files(err) => {
+                    eprintln!(
+                        "{}: Configuration error: {} (using defaults; see: lw config check)",
+                        "ERROR".red(),
+                        err.to_string().red()
+                    )
+                }
+                LoadProblem::Profile(err) => {
+                    eprintln!(
+                        "{}: Profile error: {} (using the configuration files without it)",
+                        "ERROR".red(),
+                        err.to_string().red()
+                    )
+                }
+                LoadProblem::Environment(EnvironmentProblem::Unknown(problem)) => {
+                    eprintln!("{}: Environment: {}", "WARN".yellow(), problem.yellow())
+                }
+                LoadProblem::Environment(EnvironmentProblem::Invalid(problem)) => {
+                    eprintln!("{}: Environment: {}", "ERROR".red(), problem.red())
+                }
+            }
+        }
+        loaded
+    }
+
+
+    /// Load the `lw` configuration: the defaults, overridden by the existing
+    /// configuration files (see [`Config::load_layers`]), the `profile`, if
+    /// any, then by the environment and last by the `overrides` (the command
+    /// line options). An invalid file leaves just the defaults, an unknown or
+    /// invalid profile just the files. The problems found on the way are
+    /// returned for the caller to report.
+    pub fn load_with_problems(
+        profile: Option<&str>,
+        overrides: impl FnOnce(&mut Config),
+    ) -> (Config, Vec<LoadProblem>) {
+        let mut problems = Vec::new();
+        if let Ok(config) = env::var("LW_CONFIG")
+            && !Path::new(&config).exists()
+        {
+            problems.push(LoadProblem::MissingFile(config));
+        }
+        let mut loaded = Config::load_layers().unwrap_or_else(|err| {
+            problems.push(LoadProblem::Files(err));
+            Config::default()
+        });
+        if let Some(profile) = profile
+            && let Err(err) = loaded.apply_profile(profile)
+        {
+            problems.push(LoadProblem::Profile(err));
+        }
+        problems.extend(
+            loaded
+                .apply_environment()
+                .into_iter()
+                .map(LoadProblem::Environment),
+        );
+        overrides(&mut loaded);
+        (loaded, problems)
+    }
+
+
+    /// Merge the existing configuration files over the defaults, lowest layer
+    /// first: system-wide, user, project. Each of them overrides only the
+    /// fields it sets. An invalid file is an error.
+    pub fn load_layers() -> Result<Config, Error> {
+        let mut merged = Config::default();
+        for path in Config::existing_config_paths() {
+            let (layer, fields) = Config::read_layer(&path)
+                .map_err(|err| Error::new(err.kind(), format!("{err} in file: {path}")))?;
+            merged
+                .merge(layer, &fields, &path)
+                .map_err(|err| Error::new(err.kind(), format!("{err} in file: {path}")))?;
+        }
+        Ok(merged.with_compiled_globs())
+    }
+
+
+    /// Apply the profile called `name`: the fields set by the profiles it
+    /// inherits from first, then its own. Its `paths`, or the ones of the
+    /// closest profile it inherits from, become the [`Config::profile_paths`].
+    /// An unknown or invalid profile changes nothing.
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+        let profiles = self.profiles.clone().unwrap_or_default();
+        let mut chain: Vec<(String, Config, Vec<String>, ProfileKeys)> = Vec::new();
+        let mut next = Some(name.to_string());
+        while let Some(name) = next {
+            if chain.iter().any(|(inherited, ..)| *inherited == name) {
+                return Err(invalid(format!("Profile inherits from itself: {name}")));
+            }
+            let value = profiles
+                .get(&name)
+                .ok_or_else(|| invalid(format!("Unknown profile: {name}")))?;
+            let (layer, fields, keys) = read_profile(value)
+                .map_err(|err| invalid(format!("{err} in profile: {name}")))?;
+            next = keys.inherits.clone();
+            chain.push((name, layer, fields, keys));
+        }
+        let mut applied = self.clone();
+        for (name, layer, fields, keys) in chain.into_iter().rev() {
+            if let Some(paths) = keys.paths {
+                applied.profile_paths = paths;
+            }
+            applied
+                .merge(layer, &fields, &format!("profile: {name}"))
+                .map_err(|err| invalid(format!("{err} in profile: {name}")))?;
+        }
+        *self = applied.with_compiled_globs();
+        Ok(())
+    }
+
+
+    /// Paths to watch of the selected profile, used when none are given as
+    /// arguments.
+    pub fn profile_paths(&self) -> &[String] {
+        &self.profile_paths
+    }
+
+
+    /// Read and validate the configuration file at `path`, with the names of
+    /// the fields it sets.
+    fn read_layer(path: &str) -> Result<(Config, Vec<String>), Error> {
+        let file_contents = read_to_string(path)?;
+        let layer = ron::from_str::<Config>(&file_contents)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+        layer.validate()?;
+        Ok((layer, field_names(&file_contents)))
+    }
+
+
+    /// Override the `fields` set by `layer`, which came from `origin`. List
+    /// fields named in its [`Self::append`] are appended to instead, and
+    /// profiles always are. Fields are merged as their serialized values, so
+    /// any field of [`Config`] is without naming it here.
+    fn merge(
+        &mut self,
+        mut layer: Config,
+        fields: &[String],
+        origin: &str,
+    ) -> Result<(), Error> {
+        let append = layer.append.take().unwrap_or_default();
+        let mut merged = self.field_values();
+        let mut values = layer.field_values();
+        for field in fields.iter().filter(|field| *field != "append") {
+            let key = ron::Value::String(field.clone());
+            let Some(value) = values.remove(&key) else {
+                continue;
+            };
+            let value = merge_value(merged.remove(&key), value, append.contains(field));
+            merged.insert(key, value);
+            self.origins.insert(field.clone(), origin.to_string());
+        }
+        let mut rebuilt: Config = ron::Value::Map(merged)
+            .into_rust()
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+        rebuilt.origins = std::mem::take(&mut self.origins);
+        rebuilt.profile_paths = std::mem::take(&mut self.profile_paths);
+        rebuilt.roots = std::mem::take(&mut self.roots);
+        rebuilt.ignore_file_rules = std::mem::take(&mut self.ignore_file_rules);
+        *self = rebuilt;
+        Ok(())
+    }
+
+
+    /// The serialized values of the fields of this configuration, by name.
+    fn field_values(&self) -> ron::Map {
+        match ron::from_str(&self.to_ron()) {
+            Ok(ron::Value::Map(fields)) => fields,
+            _ => ron::Map::new(),
+        }
+    }
+
+
+    /// Where the value of the field called `name` came from: the path of a
+    /// configuration file, a profile, the environment variable, the command
+    /// line option, or `default`.
+    pub fn origin(&self, name: &str) -> &str {
+        self.origins.get(name).map_or("default", String::as_str)
+    }
+
+
+    /// Record that the field called `name` was set by `origin`, like a
+    /// command line option.
+    pub fn set_origin(&mut self, name: &str, origin: &str) {
+        self.origins.insert(name.to_string(), origin.to_string());
+    }
+
+
+    /// Check values that parse fine but mean nothing to `lw`.
+    fn validate(&self) -> Result<(), Error> {
+        match self.invalid_values().as_slice() {
+            [] => Ok(()),
+            problems => Err(Error::new(ErrorKind::InvalidInput, problems.join(", "))),
+        }
+    }
+
+
+    /// Values that parse fine but mean nothing to `lw`, described.
+    fn invalid_values(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .encodings
+            .as_deref()
             .unwrap_or_default()
+            .iter()
+            .filter(|(_, label)| for_label(label).is_none())
+            .map(|(pattern, label)| {
+                format!("Unknown encoding: {label} for pattern: {pattern}")
+            })
+            .collect();
+        if let Some(append) = self.append.as_deref()
+            && !append.is_empty()
+        {
+            problems.extend(
+                append
+                    .iter()
+                    .filter(|field| !LIST_FIELDS.contains(&field.as_str()))
+                    .map(|field| {
+                        format!(
+                            "Can't append to: {field} (expected one of: {})",
+                            LIST_FIELDS.join(", ")
+                        )
+                    }),
+            );
+        }
+        problems.extend(self.rules.iter().flatten().flat_map(Rule::problems));
+        if let Some(log_level) = self.log_level.as_deref()
+            && !LOG_LEVELS.contains(&log_level.to_uppercase().as_str())
+        {
+            problems.push(format!(
+                "Unknown log_level: {log_level} (expected one of: {})",
+                LOG_LEVELS.join(", ")
+            ));
+        }
+        problems
+    }
+
+
+    /// Strictly check the configuration file at `path`, for `lw config check`.
+    /// On top of what [`Config::load_layers`] rejects, unknown fields, missing
+    /// themes, syntaxes and themes failing to load, malformed glob patterns
+    /// and an output in a nonexistent directory are reported. Returns every problem found.
+    pub fn check(path: &str) -> Vec<String> {
+        let file_contents = match read_to_string(path) {
+            Ok(file_contents) => file_contents,
+            Err(err) => return vec![err.to_string()],
+        };
+        let config = match ron::from_str::<Config>(&file_contents) {
+            Ok(config) => config,
+            Err(err) => {
+                return vec![format!(
+                    "line {}, column {}: {}",
+                    err.span.start.line, err.span.start.col, err.code
+                )];
+            }
+        };
+        let mut problems: Vec<String> = unknown_fields(&file_contents)
+            .into_iter()
+            .map(|field| format!("Unknown field: {field}"))
+            .collect();
+        problems.extend(config.invalid_values());
+        let profiles = config.profiles.clone().unwrap_or_default();
+        let known = field_names(&Config::default().to_ron());
+        for (name, value) in &profiles {
+            match read_profile(value) {
+                Ok((_, fields, keys)) => {
+                    problems.extend(
+                        fields
+                            .iter()
+                            .filter(|field| !known.contains(field))
+                            .map(|field| format!("Unknown field: {field} in profile: {name}")),
+                    );
+                    if let Some(inherits) = keys.inherits
+                        && !profiles.contains_key(&inherits)
+                    {
+                        problems.push(format!(
+                            "Unknown profile: {inherits} inherited by profile: {name}"
+                        ));
+                    }
+                }
+                Err(err) => problems.push(format!("{err} in profile: {name}")),
+            }
+        }
+        let syntax_names: Vec<&str> = config
+            .syntaxes
+            .iter()
+            .flatten()
+            .map(|(_, syntax)| syntax.as_str())
+            .chain(
+                config
+                    .rules
+                    .iter()
+                    .flatten()
+                    .filter_map(|rule| rule.syntax.as_deref()),
+            )
+            .collect();
+        problems.extend(highlight::check(
+            config.theme.as_deref(),
+            &syntax_names,
+            config.syntax_dirs.as_deref().unwrap_or_default(),
+            config.theme_dirs.as_deref().unwrap_or_default(),
+        ));
+        let patterns = [
+            &config.ignore_patterns,
+            &config.include_patterns,
+            &config.hexdump_patterns,
+        ];
+        problems.extend(
+            patterns
+                .into_iter()
+                .flat_map(|patterns| patterns.as_deref().unwrap_or_default())
+                .chain(
+                    config
+                        .encodings
+                        .iter()
+                        .flatten()
+                        .map(|(pattern, _)| pattern),
+                )
+                .chain(config.syntaxes.iter().flatten().map(|(pattern, _)| pattern))
+                .chain(config.rules.iter().flatten().map(|rule| &rule.pattern))
+                .filter_map(|pattern| glob::check(pattern).err()),
+        );
+        if let Some(output) = config.output.as_deref()
+            && let Some(directory) = Path::new(output).parent()
+            && !directory.as_os_str().is_empty()
+            && !directory.is_dir()
+        {
+            problems.push(format!("Output directory doesn't exist: {output}"));
+        }
+        problems
     }
 
 
-    /// Precompile [`Self::ignore_patterns`] into `ignore_globs`. Runs after
-    /// load/deserialization (which leaves the derived field empty) and inside
+    /// Precompile [`Self::ignore_patterns`], [`Self::include_patterns`] and
+    /// [`Self::hexdump_patterns`] into their globs. Runs after
+    /// load/deserialization (which leaves the derived fields empty) and inside
     /// [`Config::default`], so the compiled globs are always in sync.
     fn with_compiled_globs(mut self) -> Self {
-        self.ignore_globs = self
-            .ignore_patterns
+        self.ignore_globs = compile_globs(self.ignore_patterns.as_deref());
+        self.include_globs = compile_globs(self.include_patterns.as_deref());
+        self.hexdump_globs = compile_globs(self.hexdump_patterns.as_deref());
+        self.encoding_globs = self
+            .encodings
             .as_deref()
             .unwrap_or_default()
             .iter()
-            .map(|pattern| pattern.chars().collect())
+            .filter_map(|(pattern, label)| {
+                for_label(label).map(|encoding| (Glob::new(pattern), encoding))
+            })
+            .collect();
+        self.syntax_globs = self
+            .syntaxes
+            .iter()
+            .flatten()
+            .map(|(pattern, syntax)| (Glob::new(pattern), syntax.clone()))
+            .collect();
+        self.rule_globs = self
+            .rules
+            .iter()
+            .flatten()
+            .map(|rule| (Glob::new(&rule.pattern), rule.clone()))
             .collect();
         self
     }
 
 
-    /// Ignore patterns precompiled to char slices for matching (see
+    /// Ignore patterns precompiled to globs for matching (see
     /// [`Self::ignore_patterns`]).
-    pub fn ignore_globs(&self) -> &[Vec<char>] {
+    pub fn ignore_globs(&self) -> &[Glob] {
         &self.ignore_globs
     }
 
 
-    /// Candidate configuration file paths, in priority order.
-    fn config_paths() -> [String; 3] {
-        let home = env::var("HOME").unwrap_or_default();
-        [
-            format!("{home}/.lw.conf"),
-            format!("{home}/.config/lw.conf"),
-            String::from("lw.conf"),
-        ]
+    /// Rules of the ignore files read so far (see [`Self::ignore_files`]).
+    pub fn ignore_file_rules(&self) -> &IgnoreFiles {
+        &self.ignore_file_rules
+    }
+
+
+    /// Include patterns precompiled to globs (see [`Self::include_patterns`]).
+    pub fn include_globs(&self) -> &[Glob] {
+        &self.include_globs
+    }
+
+
+    /// Configured encoding of the file at `path`, if any (see
+    /// [`Self::encodings`]).
+    pub fn encoding_for(&self, path: &Path) -> Option<&'static Encoding> {
+        let pattern_path = SplitPath::new(self.pattern_path(path));
+        self.encoding_globs
+            .iter()
+            .find(|(glob, _)| glob.matches_split(&pattern_path, false))
+            .map(|&(_, encoding)| encoding)
+    }
+
+
+    /// Configured syntax of the file at `path`, if any (see
+    /// [`Self::syntaxes`]).
+    pub fn syntax_for(&self, path: &Path) -> Option<&str> {
+        let pattern_path = SplitPath::new(self.pattern_path(path));
+        self.syntax_globs
+            .iter()
+            .find(|(glob, _)| glob.matches_split(&pattern_path, false))
+            .map(|(_, syntax)| syntax.as_str())
+    }
+
+
+    /// Settings of the file at `path`, resolved from the [`Self::rules`]
+    /// matching it. Resolved once per file and cached with its state.
+    pub fn rules_for(&self, path: &Path) -> Arc<FileRules> {
+        let pattern_path = SplitPath::new(self.pattern_path(path));
+        Arc::new(FileRules::resolve(
+            self.rule_globs
+                .iter()
+                .filter(|(glob, _)| glob.matches_split(&pattern_path, false))
+                .map(|(_, rule)| rule),
+        ))
     }
 
-    /// Path of the first existing configuration file, if any.
-    pub fn existing_config_path() -> Option<String> {
-        Self::config_paths()
+
+    /// Whether the file at `path` is selected for hexdumps (see
+    /// [`Self::hexdump_patterns`]).
+    pub fn is_hexdump_file(&self, path: &Path) -> bool {
+        matches_any(&self.hexdump_globs, self.pattern_path(path), false)
+    }
+
+
+    /// Replace [`Self::include_patterns`], recompiling their globs.
+    pub fn set_include_patterns(&mut self, patterns: Vec<String>) {
+        self.include_globs = compile_globs(Some(&patterns));
+        self.include_patterns = Some(patterns);
+    }
+
+
+    /// Set the watched roots from the path arguments. A glob argument
+    /// (`/var/log/**/*.log`) is watched from its static prefix, with the rest
+    /// of it selecting the files to watch, including files created later.
+    pub fn set_roots(&mut self, arguments: &[String]) {
+        self.roots.clear();
+        for argument in arguments {
+            let (path, pattern) = match split_static_prefix(argument) {
+                Some((root, pattern)) if !Path::new(argument).exists() => {
+                    (PathBuf::from(root), Some(Glob::new(&pattern)))
+                }
+                _ => (PathBuf::from(argument), None),
+            };
+            match self.roots.iter_mut().find(|root| root.path == path) {
+                Some(root) => {
+                    match (&mut root.patterns, pattern) {
+                        (Some(patterns), Some(pattern)) => patterns.push(pattern),
+                        (patterns, _) => *patterns = None,
+                    }
+                }
+                None => {
+                    self.roots.push(Root {
+                        path,
+                        patterns: pattern.map(|pattern| vec![pattern]),
+                    })
+                }
+            }
+        }
+        self.roots
+            .sort_by_key(|root| Reverse(root.path.as_os_str().len()));
+    }
+
+
+    /// Paths of the watched roots, to walk at startup.
+    pub fn root_paths(&self) -> Vec<String> {
+        self.roots
+            .iter()
+            .map(|root| root.path.to_string_lossy().to_string())
+            .collect()
+    }
+
+
+    /// `path` relative to the innermost watched root containing it, or just
+    /// its file name when it isn't below any of them.
+    pub fn relative_path<'a>(&self, path: &'a Path) -> &'a Path {
+        self.root_of(path)
+            .and_then(|root| path.strip_prefix(root).ok())
+            .or_else(|| path.file_name().map(Path::new))
+            .unwrap_or(path)
+    }
+
+
+    /// Path that per-file patterns (hexdumps, encodings, syntaxes, rules) are
+    /// matched against: [`Self::relative_path`], or the file name of a file
+    /// given as argument.
+    fn pattern_path<'a>(&self, path: &'a Path) -> &'a Path {
+        match self.relative_path(path) {
+            relative_path if relative_path.as_os_str().is_empty() => {
+                path.file_name().map_or(path, Path::new)
+            }
+            relative_path => relative_path,
+        }
+    }
+
+
+    /// The innermost watched root containing `path`.
+    pub fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.innermost_root(path).map(|root| root.path.as_path())
+    }
+
+
+    /// Patterns selecting the files to watch below the innermost watched root
+    /// containing `path`, if it was given as glob argument.
+    pub fn root_patterns(&self, path: &Path) -> Option<&[Glob]> {
+        self.innermost_root(path)
+            .and_then(|root| root.patterns.as_deref())
+    }
+
+
+    fn innermost_root(&self, path: &Path) -> Option<&Root> {
+        self.roots.iter().find(|root| path.starts_with(&root.path))
+    }
+
+
+    /// Value of the environment variable `name`, unless unset or empty.
+    fn variable(name: &str) -> Option<String> {
+        env::var(name).ok().filter(|value| !value.is_empty())
+    }
+
+    /// Paths of the existing configuration files, lowest layer first.
+    pub fn existing_config_paths() -> Vec<String> {
+        config_paths_in(Self::variable)
             .into_iter()
-            .find(|path| Path::new(path).exists())
+            .filter(|path| Path::new(path).exists())
+            .collect()
     }
 
-    /// Path where a default configuration file is written when none exists.
+    /// Path where `lw config init` writes the template by default:
+    /// `$LW_CONFIG`, or the user's `~/.lw.conf`.
     pub fn default_config_path() -> String {
-        let [first, ..] = Self::config_paths();
-        first
-    }
-
-    /// Return path of the config, writing a default one if none exists yet.
-    pub fn get_or_create() -> String {
-        Self::existing_config_path().unwrap_or_else(|| {
-            let new_config_path = Self::default_config_path();
-            write_append(
-                &new_config_path,
-                &to_string_pretty(
-                    &Config::default(),
-                    PrettyConfig::new().new_line("\n".to_string()),
-                )
-                .unwrap_or_default(),
-            );
-            new_config_path
+        Self::variable("LW_CONFIG").unwrap_or_else(|| {
+            format!("{}/.lw.conf", Self::variable("HOME").unwrap_or_default())
         })
     }
 
 
+    /// Override fields with the `LW_<FIELD>` environment variables (like
+    /// `LW_TAIL_BYTES=4096`, `LW_THEME=InspiredGitHub`), and the log level
+    /// with `DEBUG` or `TRACE` when either is set (to anything but an empty
+    /// value, `0`, `no` or `false`). Values that parse but are
+    /// invalid (see [`Config::invalid_values`]) are rejected like unparsable
+    /// ones. Returns the variables that couldn't be applied.
+    pub fn apply_environment(&mut self) -> Vec<EnvironmentProblem> {
+        self.apply_variables(env::vars())
+    }
+
+
+    /// [`Config::apply_environment`] for the given `variables`.
+    fn apply_variables(
+        &mut self,
+        variables: impl IntoIterator<Item = (String, String)>,
+    ) -> Vec<EnvironmentProblem> {
+        let variables: Vec<(String, String)> = variables.into_iter().collect();
+        let is_set = |name: &str| {
+            variables.iter().any(|(variable, value)| {
+                variable == name && !matches!(value.trim(), "" | "0" | "no" | "false")
+            })
+        };
+        if let Some(level) = ["TRACE", "DEBUG"].into_iter().find(|level| is_set(level)) {
+            self.log_level = Some(level.to_string());
+            self.origins
+                .insert(String::from("log_level"), format!("environment: {level}"));
+        }
+        let known = field_names(&Config::default().to_ron());
+        let mut problems = Vec::new();
+        for (variable, value) in &variables {
+            let Some(field) = variable.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if field == "CONFIG" {
+                continue;
+            }
+            let field = field.to_lowercase();
+            if !known.contains(&field) {
+                problems.push(EnvironmentProblem::Unknown(format!(
+                    "{variable}: No such configuration field"
+                )));
+                continue;
+            }
+            let mut candidate = self.clone();
+            let applied = Config::parse_field(&field, value).and_then(|layer| {
+                let origin = format!("environment: {variable}");
+                candidate
+                    .merge(layer, std::slice::from_ref(&field), &origin)
+                    .map_err(|err| err.to_string())?;
+                match candidate.invalid_values().as_slice() {
+                    [] => Ok(()),
+                    invalid => Err(invalid.join(", ")),
+                }
+            });
+            match applied {
+                Ok(()) => *self = candidate,
+                Err(problem) => {
+                    problems.push(EnvironmentProblem::Invalid(format!(
+                        "{variable}: {problem}"
+                    )))
+                }
+            }
+        }
+        *self = std::mem::take(self).with_compiled_globs();
+        problems
+    }
+
+
+    /// A layer setting just the field called `name` from its textual `value`:
+    /// a RON value of the field's type, a boolean as `1`/`yes`/`0`/`no`, a
+    /// string, a comma separated list, or a list of `pattern=value` pairs.
+    fn parse_field(name: &str, value: &str) -> Result<Config, String> {
+        let list: Vec<String> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect();
+        let pairs: Option<Vec<(&str, &str)>> =
+            list.iter().map(|pair| pair.split_once('=')).collect();
+        let flag = match value {
+            "1" | "yes" => Some("true"),
+            "0" | "no" => Some("false"),
+            _ => None,
+        };
+        let candidates = [
+            Some(value.to_string()),
+            flag.map(String::from),
+            ron::to_string(value).ok(),
+            ron::to_string(&list).ok(),
+            pairs.and_then(|pairs| ron::to_string(&pairs).ok()),
+        ];
+        candidates
+            .into_iter()
+            .flatten()
+            .find_map(|candidate| {
+                ron::from_str::<Config>(&format!("({name}: Some({candidate}))")).ok()
+            })
+            .ok_or_else(|| format!("Invalid value: {value}"))
+    }
+
+
+    /// This configuration with its unset values resolved to the ones `lw`
+    /// falls back to when using them, for `lw config show`. Unset lists stay
+    /// unset, as that means something of its own (like watching all files).
+    pub fn resolved(&self) -> Config {
+        Config {
+            output: Some(self.output.clone().unwrap_or_default()),
+            log_level: Some(self.get_log_level().to_string()),
+            max_open_files: Some(self.max_open_files.unwrap_or_default()),
+            tail_bytes: Some(self.tail_bytes.unwrap_or_default()),
+            tail_lines: Some(self.tail_lines.unwrap_or_default()),
+            initial_tails: Some(self.initial_tails.unwrap_or_default()),
+            initial_tail_files: Some(self.initial_tail_files.unwrap_or_default()),
+            follow_links: Some(self.follow_links.unwrap_or_default()),
+            max_dir_depth: Some(self.max_dir_depth.unwrap_or_default()),
+            debounce_ms: Some(self.debounce_ms.unwrap_or_default()),
+            binary_files: Some(self.binary_files.unwrap_or_default()),
+            hexdump_bytes: Some(self.hexdump_bytes.unwrap_or_default()),
+            ignore_files: Some(self.ignore_files.unwrap_or_default()),
+            theme: Some(
+                self.theme
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_THEME.to_string()),
+            ),
+            ..self.clone()
+        }
+    }
+
+
+    /// This configuration as pretty RON, like in a configuration file.
+    pub fn to_ron(&self) -> String {
+        to_string_pretty(self, PrettyConfig::new().new_line("\n".to_string()))
+            .unwrap_or_default()
+    }
+
+
+    /// This configuration as RON, each field preceded by the [`Config::origin`]
+    /// of its value, for `lw config show --origin`.
+    pub fn to_ron_with_origins(&self) -> String {
+        self.to_annotated_ron(|field| Some(format!("from: {}", self.origin(field))))
+    }
+
+
+    /// The default configuration as RON, every field preceded by a comment
+    /// describing it: the template `lw config init` writes. Files layered over
+    /// others should keep only the fields they change.
+    pub fn template() -> String {
+        let annotated = Config::default().to_annotated_ron(|field| {
+            FIELD_COMMENTS
+                .iter()
+                .find(|(name, _)| *name == field)
+                .map(|(_, comment)| comment.to_string())
+        });
+        format!(
+            "// lw configuration. Unset (None) values fall back to the built-in\n\
+             // defaults; check changes with: lw config check\n{annotated}"
+        )
+    }
+
+
+    /// This configuration as RON, with a comment line before each field given
+    /// by `comment` for its name.
+    fn to_annotated_ron(&self, comment: impl Fn(&str) -> Option<String>) -> String {
+        let mut annotated = String::new();
+        for line in self.to_ron().lines() {
+            // fields are the lines indented once, the rest belongs to their values
+            if let Some((field, _)) = line
+                .strip_prefix("    ")
+                .and_then(|line| line.split_once(':'))
+                && field
+                    .chars()
+                    .all(|character| character.is_alphanumeric() || character == '_')
+                && let Some(comment) = comment(field)
+            {
+                if !annotated.ends_with("(\n") {
+                    annotated.push('\n');
+                }
+                annotated.push_str(&format!("    // {comment}\n"));
+            }
+            annotated.push_str(line);
+            annotated.push('\n');
+        }
+        annotated
+    }
+
+
+    /// Write the [`Config::template`] to `path`, unless a file is there
+    /// already.
+    pub fn init(path: &str) -> Result<(), Error> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?
+            .write_all(Config::template().as_bytes())
+    }
+
+
     /// Get LevelFilter (log level) from configuration
     pub fn get_log_level(&self) -> LevelFilter {
-        match self.log_level.as_deref().unwrap_or_default() {
+        match self
+            .log_level
+            .as_deref()
+            .unwrap_or_default()
+            .to_uppercase()
+            .as_str()
+        {
             "OFF" => LevelFilter::Off,
             "ERROR" => LevelFilter::Error,
             "WARN" => LevelFilter::Warn,
@@ -194,8 +1373,361 @@ impl Config {
 
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use super::{
+        BinaryFiles, Config, EnvironmentProblem, FileRules, LIST_FIELDS, config_paths_in,
+        field_names,
+    };
     use ron::ser::{PrettyConfig, to_string_pretty};
+    use std::path::Path;
+
+    /// A config that doesn't parse, or holds unknown values, is an error
+    /// rather than the defaults.
+    #[test]
+    fn invalid_config_file_is_rejected() {
+        let path = std::env::temp_dir().join(format!("lw_config_test_{}", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let ron = to_string_pretty(&Config::default(), PrettyConfig::new()).unwrap();
+        std::fs::write(&path, &ron).unwrap();
+        assert!(Config::read_layer(&path).is_ok());
+
+        std::fs::write(&path, ron.replacen("(", "((", 1)).unwrap();
+        assert!(Config::read_layer(&path).is_err());
+
+        let unknown_encoding = ron.replace(
+            "encodings: None",
+            "encodings: Some([(\"*.log\", \"no-such-encoding\")])",
+        );
+        std::fs::write(&path, unknown_encoding).unwrap();
+        assert!(Config::read_layer(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// The fields a layer may append to are exactly the ones holding lists:
+    /// those an empty list deserializes into.
+    #[test]
+    fn list_fields_are_the_fields_holding_lists() {
+        let mut lists: Vec<String> = field_names(&Config::default().to_ron())
+            .into_iter()
+            .filter(|field| {
+                field != "append"
+                    && ron::from_str::<Config>(&format!("({field}: Some([]))")).is_ok()
+            })
+            .collect();
+        lists.sort();
+        let mut list_fields = LIST_FIELDS;
+        list_fields.sort();
+        assert_eq!(lists, list_fields);
+    }
+
+    /// Each layer overrides only the fields it sets, appending to the lists
+    /// it names in `append`, and the origin of every value is kept.
+    #[test]
+    fn layers_override_only_their_fields() {
+        let directory = std::env::temp_dir();
+        let user = directory.join(format!("lw_layer_user_{}", std::process::id()));
+        let project = directory.join(format!("lw_layer_project_{}", std::process::id()));
+        let (user, project) = (
+            user.to_string_lossy().to_string(),
+            project.to_string_lossy().to_string(),
+        );
+        std::fs::write(
+            &user,
+            "(theme: Some(\"InspiredGitHub\"), ignore_patterns: Some([\"*.tmp\"]))",
+        )
+        .unwrap();
+        std::fs::write(
+            &project,
+            "(ignore_patterns: Some([\"target/\"]), append: Some([\"ignore_patterns\"]))",
+        )
+        .unwrap();
+
+        let mut merged = Config::default();
+        for path in [&user, &project] {
+            let (layer, fields) = Config::read_layer(path).unwrap();
+            merged.merge(layer, &fields, path).unwrap();
+        }
+        let merged = merged.with_compiled_globs();
+        assert_eq!(merged.theme.as_deref(), Some("InspiredGitHub"));
+        assert_eq!(
+            merged.ignore_patterns,
+            Some(vec!["*.tmp".to_string(), "target/".to_string()])
+        );
+        assert_eq!(merged.ignore_globs().len(), 2);
+        assert_eq!(merged.tail_bytes, Config::default().tail_bytes);
+        assert_eq!(merged.origin("theme"), user);
+        assert_eq!(merged.origin("ignore_patterns"), project);
+        assert_eq!(merged.origin("tail_bytes"), "default");
+        let with_origins = merged.to_ron_with_origins();
+        assert!(with_origins.contains(&format!("// from: {project}")));
+        assert_eq!(
+            with_origins.matches("// from:").count(),
+            field_names(&Config::default().to_ron()).len()
+        );
+        let _ = std::fs::remove_file(&user);
+        let _ = std::fs::remove_file(&project);
+    }
+
+    /// `lw config check` reports every problem of a config that still parses,
+    /// and the position of a syntax error.
+    #[test]
+    fn strict_check_reports_problems() {
+        let path = std::env::temp_dir().join(format!("lw_check_test_{}", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let ron = to_string_pretty(&Config::default(), PrettyConfig::new()).unwrap();
+        std::fs::write(&path, &ron).unwrap();
+        assert_eq!(Config::check(&path), Vec::<String>::new());
+
+        let broken = ron
+            .replace("log_level: Some(\"INFO\")", "log_level: Some(\"LOUD\")")
+            .replace(
+                "theme: Some(",
+                "theme: Some(\"no such theme\"), themes: Some(",
+            )
+            .replace(
+                "include_patterns: None",
+                "include_patterns: Some([\"*.[ch\"])",
+            );
+        std::fs::write(&path, broken).unwrap();
+        let problems = Config::check(&path);
+        assert_eq!(problems.len(), 4, "{problems:?}");
+        assert!(
+            problems
+                .iter()
+                .any(|problem| problem == "Unknown field: themes")
+        );
+
+        std::fs::write(&path, "(\n    output: Some(\"x\"),\n    tail_bytes: ]\n)").unwrap();
+        let problems = Config::check(&path);
+        assert!(problems[0].starts_with("line 3, column"), "{problems:?}");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// `LW_CONFIG` is the only layer when set; otherwise system-wide files
+    /// come first and the project's last.
+    #[test]
+    fn config_file_candidates() {
+        let variables = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(variable, _)| *variable == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            config_paths_in(variables(&[("LW_CONFIG", "/tmp/my.conf"), ("HOME", "/h")])),
+            vec!["/tmp/my.conf"]
+        );
+        assert_eq!(
+            config_paths_in(variables(&[("HOME", "/h")])),
+            vec![
+                "/etc/lw.conf",
+                "/etc/xdg/lw.conf",
+                "/h/.config/lw.conf",
+                "/h/.lw.conf",
+                "lw.conf"
+            ]
+        );
+        assert_eq!(
+            config_paths_in(variables(&[
+                ("HOME", "/h"),
+                ("XDG_CONFIG_HOME", "/xdg"),
+                ("XDG_CONFIG_DIRS", "/a:/b"),
+            ]))[1..4],
+            ["/b/lw.conf", "/a/lw.conf", "/xdg/lw.conf"]
+        );
+    }
+
+    /// `DEBUG` or `TRACE` set to nothing (or switched off) is the same as unset.
+    #[test]
+    fn switched_off_debug_variables_keep_the_log_level() {
+        let mut config = Config::default();
+        let problems = config.apply_variables([
+            ("DEBUG".to_string(), "0".to_string()),
+            ("TRACE".to_string(), String::new()),
+        ]);
+        assert!(problems.is_empty());
+        assert_eq!(config.log_level, Config::default().log_level);
+        assert!(!config.origins.contains_key("log_level"));
+    }
+
+    /// `LW_<FIELD>` variables override single fields; `TRACE` and `DEBUG` the
+    /// log level.
+    #[test]
+    fn environment_overrides_fields() {
+        let mut config = Config::default();
+        let problems = config.apply_variables([
+            ("LW_TAIL_BYTES".to_string(), "4096".to_string()),
+            ("LW_THEME".to_string(), "InspiredGitHub".to_string()),
+            (
+                "LW_INCLUDE_PATTERNS".to_string(),
+                "*.log, *.err".to_string(),
+            ),
+            ("LW_ENCODINGS".to_string(), "*.txt=latin1".to_string()),
+            (
+                "LW_SYNTAXES".to_string(),
+                "Jenkinsfile=Groovy,*.conf=nginx".to_string(),
+            ),
+            ("LW_FOLLOW_LINKS".to_string(), "no".to_string()),
+            ("LW_CONFIG".to_string(), "/tmp/my.conf".to_string()),
+            ("DEBUG".to_string(), "1".to_string()),
+            ("LW_MAX_DIR_DEPTH".to_string(), "deep".to_string()),
+            ("LW_NOTHING".to_string(), "1".to_string()),
+            ("LW_LOG_LEVEL".to_string(), "loud".to_string()),
+            (
+                "LW_ENCODINGS".to_string(),
+                "*.txt=latin1,*.x=klingon".to_string(),
+            ),
+            ("LW_RULES".to_string(), "*.log".to_string()),
+        ]);
+        assert_eq!(config.tail_bytes, Some(4096));
+        assert_eq!(config.theme.as_deref(), Some("InspiredGitHub"));
+        assert_eq!(config.include_globs().len(), 2);
+        assert!(config.encoding_for(Path::new("x.txt")).is_some());
+        assert_eq!(
+            config.syntax_for(Path::new("/ci/Jenkinsfile")),
+            Some("Groovy")
+        );
+        assert_eq!(config.syntax_for(Path::new("x.txt")), None);
+        assert_eq!(config.follow_links, Some(false));
+        assert_eq!(config.log_level.as_deref(), Some("DEBUG"));
+        assert_eq!(config.max_dir_depth, Config::default().max_dir_depth);
+        assert_eq!(
+            problems,
+            [
+                EnvironmentProblem::Invalid(
+                    "LW_MAX_DIR_DEPTH: Invalid value: deep".to_string()
+                ),
+                EnvironmentProblem::Unknown(
+                    "LW_NOTHING: No such configuration field".to_string()
+                ),
+                EnvironmentProblem::Invalid(
+                    "LW_LOG_LEVEL: Unknown log_level: loud (expected one of: OFF, ERROR, WARN, \
+                     INFO, DEBUG, TRACE)"
+                        .to_string()
+                ),
+                EnvironmentProblem::Invalid(
+                    "LW_ENCODINGS: Unknown encoding: klingon for pattern: *.x".to_string()
+                ),
+                EnvironmentProblem::Invalid(
+                    "LW_RULES: Invalid value: *.log".to_string()
+                ),
+            ]
+        );
+        assert_eq!(config.origin("encodings"), "environment: LW_ENCODINGS");
+    }
+
+    /// Any field, lists of structs too, takes its value as RON.
+    #[test]
+    fn environment_takes_ron_values() {
+        let mut config = Config::default();
+        let problems = config.apply_variables([
+            (
+                "LW_RULES".to_string(),
+                r#"[(pattern: "*.err", color: Some("red"))]"#.to_string(),
+            ),
+            ("LW_LOG_LEVEL".to_string(), "debug".to_string()),
+            ("LW_BINARY_FILES".to_string(), "skip".to_string()),
+        ]);
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(config.rules.as_ref().map(Vec::len), Some(1));
+        assert_eq!(config.get_log_level(), log::LevelFilter::Debug);
+        assert_eq!(config.binary_files, Some(BinaryFiles::Skip));
+    }
+
+    /// A profile applies over the configuration after the profiles it
+    /// inherits from, and brings its paths to watch.
+    #[test]
+    fn profiles_inherit_and_bring_paths() {
+        let ron = r#"(
+            tail_lines: Some(5),
+            profiles: Some({
+                "base": (paths: Some(["/var/log"]), ignore_patterns: Some(["*.gz"]), tail_lines: Some(20)),
+                "web": (inherits: Some("base"), include_patterns: Some(["*.log"]), theme: Some("InspiredGitHub")),
+                "loop": (inherits: Some("loop")),
+            }),
+        )"#;
+        let (layer, fields) = (ron::from_str::<Config>(ron).unwrap(), field_names(ron));
+        let mut config = Config::default();
+        config.merge(layer, &fields, "lw.conf").unwrap();
+        let mut web = config.clone();
+        web.apply_profile("web").unwrap();
+        assert_eq!(web.profile_paths(), ["/var/log"]);
+        assert_eq!(web.tail_lines, Some(20));
+        assert_eq!(web.ignore_patterns, Some(vec!["*.gz".to_string()]));
+        assert_eq!(web.include_patterns, Some(vec!["*.log".to_string()]));
+        assert_eq!(web.origin("tail_lines"), "profile: base");
+        assert_eq!(web.origin("theme"), "profile: web");
+        assert!(config.clone().apply_profile("loop").is_err());
+        let files = config.clone();
+        assert!(config.apply_profile("nope").is_err());
+        assert_eq!(config.to_ron(), files.to_ron(), "the files stay in effect");
+        assert_eq!(config.tail_lines, Some(5));
+    }
+
+    /// Rules match paths relative to the watched roots, every matching one
+    /// applying in order.
+    #[test]
+    fn rules_resolve_per_file() {
+        let ron = r#"(rules: Some([
+            (pattern: "*.log", label: Some("logs"), tail_lines: Some(5)),
+            (pattern: "nginx/**", show: Some(false), tail_lines: Some(50)),
+        ]))"#;
+        let mut config = ron::from_str::<Config>(ron).unwrap().with_compiled_globs();
+        config.set_roots(&["/nonexistent/lw".to_string()]);
+        let app = config.rules_for(Path::new("/nonexistent/lw/app.log"));
+        assert_eq!((app.show, app.tail_lines), (true, Some(5)));
+        assert_eq!(app.label.as_deref(), Some("logs"));
+        let nginx = config.rules_for(Path::new("/nonexistent/lw/nginx/access.log"));
+        assert_eq!((nginx.show, nginx.tail_lines), (false, Some(50)));
+        assert_eq!(nginx.label.as_deref(), Some("logs"));
+        let other = config.rules_for(Path::new("/nonexistent/lw/data.db"));
+        assert_eq!(*other, FileRules::default());
+    }
+
+    /// The template documents every field, and reads back as the defaults.
+    #[test]
+    fn template_comments_every_field() {
+        let template = Config::template();
+        let parsed: Config = ron::from_str(&template).unwrap();
+        assert_eq!(parsed.to_ron(), Config::default().to_ron());
+        let fields = field_names(&Config::default().to_ron());
+        assert_eq!(template.matches("\n    // ").count(), fields.len());
+    }
+
+    /// Glob arguments are watched from their static prefix, with the rest
+    /// selecting files below it; a plain path argument watches all of it.
+    #[test]
+    fn glob_arguments_split_into_roots_and_patterns() {
+        let mut config = Config::default();
+        config.set_roots(&[
+            "/nonexistent/lw/**/*.log".to_string(),
+            "/nonexistent/lw/*.err".to_string(),
+            "/nonexistent/other".to_string(),
+        ]);
+        assert_eq!(
+            config.root_paths(),
+            vec!["/nonexistent/other", "/nonexistent/lw"]
+        );
+        let file = Path::new("/nonexistent/lw/app/x.log");
+        assert_eq!(config.root_of(file), Some(Path::new("/nonexistent/lw")));
+        assert_eq!(config.relative_path(file), Path::new("app/x.log"));
+        assert_eq!(config.root_patterns(file).map(<[_]>::len), Some(2));
+        assert!(
+            config
+                .root_patterns(Path::new("/nonexistent/other/x"))
+                .is_none()
+        );
+
+        config.set_roots(&[
+            "/nonexistent/lw/*.log".to_string(),
+            "/nonexistent/lw".to_string(),
+        ]);
+        assert!(
+            config.root_patterns(file).is_none(),
+            "plain path watches all"
+        );
+    }
 
     /// The default config precompiles its ignore globs eagerly.
     #[test]
@@ -217,6 +1749,37 @@ mod tests {
         assert!(ron.contains("ignore_patterns"));
     }
 
+    /// Unset values are shown as what `lw` falls back to, keeping origins.
+    #[test]
+    fn resolved_config_fills_unset_values() {
+        let mut config = Config {
+            log_level: None,
+            max_open_files: None,
+            theme: None,
+            include_patterns: None,
+            ..Config::default()
+        };
+        config
+            .origins
+            .insert("tail_lines".to_string(), "lw.conf".to_string());
+        let resolved = config.resolved();
+        assert_eq!(resolved.log_level.as_deref(), Some("INFO"));
+        assert_eq!(resolved.max_open_files, Some(0));
+        assert_eq!(resolved.theme, Config::default().theme);
+        assert_eq!(resolved.include_patterns, None);
+        assert_eq!(resolved.origin("tail_lines"), "lw.conf");
+    }
+
+    /// `binary_files` is written and read by the name of its mode.
+    #[test]
+    fn binary_files_mode_is_configured_by_name() {
+        let ron = to_string_pretty(&Config::default(), PrettyConfig::new()).unwrap();
+        assert!(ron.contains("binary_files: Some(\"summary\")"), "{ron}");
+        let skip: Config = ron::from_str(&ron.replace("\"summary\"", "\"skip\"")).unwrap();
+        assert_eq!(skip.binary_files, Some(BinaryFiles::Skip));
+        assert!(ron::from_str::<Config>(&ron.replace("\"summary\"", "\"bogus\"")).is_err());
+    }
+
     /// A config parsed from RON (which leaves the derived field empty) has its
     /// globs recompiled by `with_compiled_globs`, matching its patterns.
     #[test]
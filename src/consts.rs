@@ -12,6 +12,39 @@ pub const MAX_OPEN_FILES: usize = 2048;
 /// Read tail of this length from large files
 pub const TAIL_BYTES: u64 = 1024;
 
+/// Window in which events for the same path are coalesced into one read
+pub const DEBOUNCE_MS: u64 = 50;
+
+/// Longest wait for watcher events before housekeeping (exited processes,
+/// finished commands) is done anyway
+pub const HOUSEKEEPING_INTERVAL_MS: u64 = 1000;
+
+/// Longest wait for watcher events while stream sources are open, since their
+/// lines don't wake the watcher
+pub const STREAM_POLL_INTERVAL_MS: u64 = 20;
+
+/// Interval at which files evicted from the watch budget are polled
+pub const EVICTED_POLL_INTERVAL_MS: u64 = 2000;
+
+/// Bytes at the start of a file sniffed to tell binary files from text
+pub const BINARY_SNIFF_BYTES: usize = 8192;
+
+/// Percentage of invalid UTF-8 bytes above which sniffed content is binary
+pub const BINARY_INVALID_UTF8_PERCENT: usize = 30;
+
+/// Most bytes shown in a hexdump of a single file change
+pub const HEXDUMP_BYTES: usize = 256;
+
+/// Bytes at the end of a file in a non-UTF-8 encoding decoded to find its
+/// last lines
+pub const ENCODED_TAIL_WINDOW: u64 = 65536;
+
+/// Lines of tail shown for existing files at startup and in one-shot mode
+pub const TAIL_LINES: usize = 10;
+
+/// Most recently modified files whose tails are shown at startup
+pub const INITIAL_TAIL_FILES: usize = 16;
+
 /// Filename glob patterns ignored by default. These are transient files that
 /// tools create and immediately rename away (e.g. `rustfmt` writes
 /// `foo.rs.tmp.PID.HASH` then renames it over `foo.rs`), plus common editor
@@ -26,5 +59,9 @@ pub const DEFAULT_IGNORE_PATTERNS: &[&str] = &[
     "*.bak",   // backups
 ];
 
+/// Ignore files read in every watched directory when ignore files are
+/// respected, in increasing precedence
+pub const IGNORE_FILE_NAMES: &[&str] = &[".gitignore", ".ignore", ".lwignore"];
+
 /// Default syntect theme used for syntax-highlighted file output.
 pub const DEFAULT_THEME: &str = "base16-ocean.dark";
@@ -0,0 +1,164 @@
+//! Per-path coalescing of watcher events.
+//!
+//! A file written a thousand times per second produces a thousand vnode
+//! events. Instead of reading it after each of them, the first event for a
+//! path opens a debounce window and all further events for that path within
+//! the window are folded into it: the path is handled once, when its window
+//! closes.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+
+/// Paths with pending events, each with the instant its first event arrived.
+#[derive(Debug)]
+pub struct Debouncer {
+    window: Duration,
+    pending: HashMap<String, Instant>,
+}
+
+
+impl Debouncer {
+    /// Create a debouncer coalescing events within `window`. A zero window
+    /// makes every path due right away.
+    pub fn new(window: Duration) -> Self {
+        Debouncer {
+            window,
+            pending: HashMap::new(),
+        }
+    }
+
+
+    /// Coalesce events within `window` from now on; pending paths are kept.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+
+    /// Record an event for `path` that arrived at `now`. Only the first event
+    /// of a burst opens the window; later ones don't extend it, so a
+    /// constantly written file is still handled once per window.
+    pub fn add(&mut self, path: &str, now: Instant) {
+        if !self.pending.contains_key(path) {
+            self.pending.insert(path.to_string(), now);
+        }
+    }
+
+
+    /// Take the paths whose window has closed by `now`, in the order their
+    /// first events arrived.
+    pub fn take_due(&mut self, now: Instant) -> Vec<String> {
+        let mut due: Vec<(Instant, String)> = self
+            .pending
+            .iter()
+            .filter(|&(_, &first_seen)| first_seen + self.window <= now)
+            .map(|(path, &first_seen)| (first_seen, path.clone()))
+            .collect();
+        due.sort();
+        due.into_iter()
+            .map(|(_, path)| {
+                self.pending.remove(&path);
+                path
+            })
+            .collect()
+    }
+
+
+    /// How long to wait for events at `now`: until the earliest pending
+    /// window closes, but never longer than `max_wait`.
+    pub fn timeout(&self, now: Instant, max_wait: Duration) -> Duration {
+        self.pending
+            .values()
+            .map(|&first_seen| (first_seen + self.window).saturating_duration_since(now))
+            .min()
+            .map_or(max_wait, |until_due| until_due.min(max_wait))
+    }
+
+
+    /// Number of paths with pending events.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+
+    /// Whether no events are pending.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Debouncer;
+    use std::time::{Duration, Instant};
+
+    const WINDOW: Duration = Duration::from_millis(50);
+    const MAX_WAIT: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn burst_of_events_is_handled_once_per_window() {
+        let mut debouncer = Debouncer::new(WINDOW);
+        let start = Instant::now();
+        for millis in 0..40 {
+            debouncer.add("app.log", start + Duration::from_millis(millis));
+        }
+        assert!(
+            debouncer
+                .take_due(start + Duration::from_millis(49))
+                .is_empty()
+        );
+        assert_eq!(debouncer.take_due(start + WINDOW), vec!["app.log"]);
+        assert!(debouncer.is_empty());
+    }
+
+    #[test]
+    fn later_events_do_not_extend_the_window() {
+        let mut debouncer = Debouncer::new(WINDOW);
+        let start = Instant::now();
+        debouncer.add("app.log", start);
+        debouncer.add("app.log", start + Duration::from_millis(45));
+        assert_eq!(debouncer.take_due(start + WINDOW), vec!["app.log"]);
+    }
+
+    #[test]
+    fn due_paths_come_in_arrival_order() {
+        let mut debouncer = Debouncer::new(WINDOW);
+        let start = Instant::now();
+        debouncer.add("b.log", start);
+        debouncer.add("a.log", start + Duration::from_millis(1));
+        debouncer.add("c.log", start + Duration::from_millis(30));
+        assert_eq!(
+            debouncer.take_due(start + Duration::from_millis(60)),
+            vec!["b.log", "a.log"]
+        );
+        assert_eq!(debouncer.len(), 1);
+    }
+
+    #[test]
+    fn zero_window_makes_paths_due_immediately() {
+        let mut debouncer = Debouncer::new(Duration::ZERO);
+        let now = Instant::now();
+        debouncer.add("app.log", now);
+        assert_eq!(debouncer.timeout(now, MAX_WAIT), Duration::ZERO);
+        assert_eq!(debouncer.take_due(now), vec!["app.log"]);
+    }
+
+    #[test]
+    fn timeout_waits_for_the_earliest_window() {
+        let mut debouncer = Debouncer::new(WINDOW);
+        let start = Instant::now();
+        assert_eq!(debouncer.timeout(start, MAX_WAIT), MAX_WAIT);
+        debouncer.add("app.log", start);
+        assert_eq!(
+            debouncer.timeout(start + Duration::from_millis(20), MAX_WAIT),
+            Duration::from_millis(30)
+        );
+        assert_eq!(
+            debouncer.timeout(start, Duration::from_millis(10)),
+            Duration::from_millis(10)
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Text encodings other than UTF-8.
+//!
+//! Windows-originated and legacy logs come as UTF-16 or single/multi byte
+//! legacy encodings. A file's encoding is taken from its byte order mark, or
+//! else from the `encodings` configured for its path; files with neither are
+//! read as UTF-8. Only decoding happens here: positions of watched files stay
+//! byte offsets, while the lines handed out are proper UTF-8.
+
+use crate::consts::ENCODED_TAIL_WINDOW;
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+
+/// Look up an encoding by its WHATWG label (`utf-16le`, `latin1`,
+/// `shift_jis`, `windows-1252`, ...).
+pub fn for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+
+/// Encoding of the file at `path` and the length of its byte order mark:
+/// the mark wins over the `configured` encoding. `None` for UTF-8 files,
+/// which are read as they are.
+pub fn file_encoding(
+    path: &Path,
+    configured: Option<&'static Encoding>,
+) -> Option<(&'static Encoding, u64)> {
+    let mut start = [0u8; 3];
+    let read = File::open(path)
+        .and_then(|mut file| file.read(&mut start))
+        .unwrap_or(0);
+    match Encoding::for_bom(&start[..read]) {
+        Some((encoding, bom_length)) => {
+            (encoding != UTF_8).then_some((encoding, bom_length as u64))
+        }
+        None => {
+            configured
+                .filter(|&encoding| encoding != UTF_8)
+                .map(|encoding| (encoding, 0))
+        }
+    }
+}
+
+
+/// Read the file at `path` from byte `position` to its end, decoded from
+/// `encoding` to UTF-8 lines. A position within the byte order mark, or in the
+/// middle of a UTF-16 code unit, is moved forward to where text starts.
+pub fn read_decoded_lines(
+    path: &Path,
+    position: u64,
+    encoding: &'static Encoding,
+    bom_length: u64,
+) -> io::Result<Vec<String>> {
+    let mut position = position.max(bom_length);
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        position += (position - bom_length) % 2;
+    }
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(position))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let (text, _) = encoding.decode_without_bom_handling(&bytes);
+    Ok(text.lines().map(String::from).collect())
+}
+
+
+/// The last `tail_lines` lines of the `file_size` long file at `path`, decoded
+/// from `encoding`, with the byte offset they were read from. Only the last
+/// [`ENCODED_TAIL_WINDOW`] bytes are looked at.
+pub fn read_decoded_last_lines(
+    path: &Path,
+    file_size: u64,
+    tail_lines: usize,
+    encoding: &'static Encoding,
+    bom_length: u64,
+) -> io::Result<(u64, Vec<String>)> {
+    let position = file_size.saturating_sub(ENCODED_TAIL_WINDOW);
+    let mut lines = read_decoded_lines(path, position, encoding, bom_length)?;
+    let lines = lines.split_off(lines.len().saturating_sub(tail_lines));
+    Ok((position.max(bom_length), lines))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{file_encoding, for_label, read_decoded_last_lines, read_decoded_lines};
+    use encoding_rs::{SHIFT_JIS, UTF_16LE, WINDOWS_1252};
+    use std::{fs, path::PathBuf};
+
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "lw_encoding_test_{}_{}",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        bytes
+    }
+
+    #[test]
+    fn byte_order_mark_wins_over_configured_encoding() {
+        let path = temp_file("bom", &utf16le("zażółć\r\n"));
+        assert_eq!(
+            file_encoding(&path, Some(WINDOWS_1252)),
+            Some((UTF_16LE, 2))
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn plain_utf8_is_not_decoded() {
+        let path = temp_file("utf8", "plain\n".as_bytes());
+        assert_eq!(file_encoding(&path, None), None);
+        assert_eq!(file_encoding(&path, Some(SHIFT_JIS)), Some((SHIFT_JIS, 0)));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn utf16_lines_are_decoded_from_byte_offsets() {
+        let content = utf16le("first\r\nsecond\r\n");
+        let path = temp_file("utf16", &content);
+        assert_eq!(
+            read_decoded_lines(&path, 0, UTF_16LE, 2).unwrap(),
+            vec!["first", "second"]
+        );
+        // "first\r\n" is 7 code units after the 2 byte mark
+        assert_eq!(
+            read_decoded_lines(&path, 16, UTF_16LE, 2).unwrap(),
+            vec!["second"]
+        );
+        // an odd offset is moved to the next code unit
+        assert_eq!(
+            read_decoded_lines(&path, 15, UTF_16LE, 2).unwrap(),
+            vec!["second"]
+        );
+        assert_eq!(
+            read_decoded_last_lines(&path, content.len() as u64, 1, UTF_16LE, 2)
+                .unwrap()
+                .1,
+            vec!["second"]
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn legacy_encodings_by_label() {
+        let path = temp_file("latin1", b"caf\xe9\n");
+        let latin1 = for_label("latin1").unwrap();
+        assert_eq!(
+            read_decoded_lines(&path, 0, latin1, 0).unwrap(),
+            vec!["café"]
+        );
+        assert_eq!(for_label("shift_jis"), Some(SHIFT_JIS));
+        assert_eq!(for_label("no-such-encoding"), None);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,538 @@
+//! Path globs for ignore (and include) patterns.
+//!
+//! Supported syntax, close to `.gitignore`:
+//! - `*` matches any run of characters within one path component, `?` a
+//!   single character;
+//! - `[abc]`, `[a-z]` match one character of the class, `[!abc]` (or `[^abc]`)
+//!   one character outside of it;
+//! - `{a,b}` matches any of the comma separated alternatives (they may nest);
+//! - `**` as a whole component matches any number of components, including
+//!   none;
+//! - `\` makes the next character literal.
+//!
+//! A pattern without a `/` matches a single component at any depth (`*.tmp`,
+//! `node_modules`). A pattern containing a `/` is anchored to the watched root
+//! (`/target`, `logs/*.log`, `**/cache/**`). A trailing `/` makes the pattern
+//! match directories only (`.git/`). A path also matches when any of its
+//! parent directories matches, so everything below an excluded directory is
+//! excluded with it.
+
+use std::path::{Component, Path};
+
+
+/// A single character matcher within a path component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// `*`: any run of characters.
+    Any,
+
+    /// `?`: exactly one character.
+    One,
+
+    /// `[...]`: one character within (or, negated, outside of) the ranges.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+
+    /// Any other character matches itself.
+    Literal(char),
+}
+
+
+/// A pattern component, matched against one path component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// `**`: any number of path components.
+    AnyComponents,
+
+    /// A component pattern.
+    Name(Vec<Token>),
+}
+
+
+/// A path split into its normal components once, to be matched against many
+/// globs with [`Glob::matches_split`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPath(Vec<Vec<char>>);
+
+
+impl SplitPath {
+    /// Split `path` into its normal components (skipping `.`, `/` and the
+    /// like).
+    pub fn new(path: &Path) -> Self {
+        SplitPath(
+            path.components()
+                .filter_map(|component| {
+                    match component {
+                        Component::Normal(name) => {
+                            Some(name.to_string_lossy().chars().collect())
+                        }
+                        _ => None,
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+
+/// A compiled path glob. Parse once with [`Glob::new`], then match paths with
+/// [`Glob::matches`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    /// One segment list per brace alternative.
+    alternatives: Vec<Vec<Segment>>,
+
+    /// Pattern had a trailing `/`.
+    directories_only: bool,
+}
+
+
+impl Glob {
+    /// Compile `pattern`. Never fails: unbalanced `[` and `{` are taken
+    /// literally.
+    pub fn new(pattern: &str) -> Self {
+        let directories_only = pattern.len() > 1 && pattern.ends_with('/');
+        let pattern = if directories_only {
+            pattern.trim_end_matches('/')
+        } else {
+            pattern
+        };
+        let alternatives = expand_braces(pattern)
+            .iter()
+            .map(|alternative| compile(alternative))
+            .collect();
+        Glob {
+            alternatives,
+            directories_only,
+        }
+    }
+
+
+    /// Whether `relative_path` (relative to the watched root) or any of its
+    /// parent directories matches. `is_dir` tells whether the path itself is a
+    /// directory, for directory-only patterns.
+    pub fn matches(&self, relative_path: &Path, is_dir: bool) -> bool {
+        self.matches_split(&SplitPath::new(relative_path), is_dir)
+    }
+
+
+    /// [`Self::matches`] for a path split already.
+    pub fn matches_split(&self, relative_path: &SplitPath, is_dir: bool) -> bool {
+        let components = &relative_path.0;
+        (1..=components.len()).any(|length| {
+            self.matches_components(&components[..length], length < components.len() || is_dir)
+        })
+    }
+
+
+    /// Whether `relative_path` itself matches, regardless of its parent
+    /// directories.
+    pub fn matches_exactly(&self, relative_path: &Path, is_dir: bool) -> bool {
+        let SplitPath(components) = SplitPath::new(relative_path);
+        !components.is_empty() && self.matches_components(&components, is_dir)
+    }
+
+
+    fn matches_components(&self, components: &[Vec<char>], is_dir: bool) -> bool {
+        (is_dir || !self.directories_only)
+            && self
+                .alternatives
+                .iter()
+                .any(|segments| segments_match(components, segments))
+    }
+}
+
+
+/// Whether any of `globs` matches `relative_path` (see [`Glob::matches`]). The
+/// path is split once for all of them.
+pub fn matches_any(globs: &[Glob], relative_path: &Path, is_dir: bool) -> bool {
+    let relative_path = SplitPath::new(relative_path);
+    globs
+        .iter()
+        .any(|glob| glob.matches_split(&relative_path, is_dir))
+}
+
+
+/// Check `pattern` for mistakes [`Glob::new`] forgives by taking them
+/// literally: an empty pattern, a `[` or `{` never closed, a `}` never opened,
+/// or a trailing `\` escaping nothing.
+pub fn check(pattern: &str) -> Result<(), String> {
+    if pattern.is_empty() {
+        return Err(String::from("empty pattern"));
+    }
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut depth = 0usize;
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' if index + 1 == chars.len() => {
+                return Err(format!("trailing \\ in pattern: {pattern}"));
+            }
+            '\\' => index += 1,
+            '[' => {
+                match parse_class(&chars[index + 1..]) {
+                    Some((_, consumed)) => index += consumed,
+                    None => return Err(format!("unclosed [ in pattern: {pattern}")),
+                }
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => return Err(format!("unopened }} in pattern: {pattern}")),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        index += 1;
+    }
+    match depth {
+        0 => Ok(()),
+        _ => Err(format!("unclosed {{ in pattern: {pattern}")),
+    }
+}
+
+
+/// Split a path argument containing glob characters into its static prefix
+/// (the directory to watch) and the rest, anchored to that directory:
+/// `/var/log/**/*.log` -> (`/var/log`, `/**/*.log`). A pattern without a static
+/// prefix is relative to the current directory. Returns `None` for plain paths.
+pub fn split_static_prefix(argument: &str) -> Option<(String, String)> {
+    let components: Vec<&str> = argument.split('/').collect();
+    let first_glob = components
+        .iter()
+        .position(|component| component.contains(['*', '?', '[', '{']))?;
+    let root = match components[..first_glob].join("/") {
+        prefix if prefix.is_empty() && argument.starts_with('/') => "/".to_string(),
+        prefix if prefix.is_empty() => ".".to_string(),
+        prefix => prefix,
+    };
+    Some((root, format!("/{}", components[first_glob..].join("/"))))
+}
+
+
+/// Expand the first top level `{a,b}` group of `pattern` into one pattern per
+/// alternative, recursively, so the result contains no brace groups.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut depth = 0;
+    let mut open = None;
+    let mut commas = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' => index += 1,
+            '{' => {
+                if depth == 0 {
+                    open = Some(index);
+                    commas.clear();
+                }
+                depth += 1;
+            }
+            ',' if depth == 1 => commas.push(index),
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0
+                    && let Some(start) = open
+                {
+                    let prefix: String = chars[..start].iter().collect();
+                    let suffix: String = chars[index + 1..].iter().collect();
+                    let mut bounds = vec![start];
+                    bounds.extend(&commas);
+                    bounds.push(index);
+                    return bounds
+                        .windows(2)
+                        .flat_map(|bound| {
+                            let alternative: String =
+                                chars[bound[0] + 1..bound[1]].iter().collect();
+                            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    vec![pattern.to_string()]
+}
+
+
+/// Compile a brace-free pattern into its segments. Patterns without a `/` are
+/// unanchored: they match at any depth, as if prefixed with `**/`.
+fn compile(pattern: &str) -> Vec<Segment> {
+    let anchored = pattern.contains('/');
+    let mut segments = Vec::new();
+    if !anchored {
+        segments.push(Segment::AnyComponents);
+    }
+    segments.extend(
+        pattern
+            .trim_start_matches('/')
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(|component| {
+                match component {
+                    "**" => Segment::AnyComponents,
+                    _ => Segment::Name(tokenize(component)),
+                }
+            }),
+    );
+    segments
+}
+
+
+/// Split one pattern component into tokens.
+fn tokenize(component: &str) -> Vec<Token> {
+    let chars: Vec<char> = component.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index] {
+            '*' => {
+                // `**` inside a component is just `*`
+                if tokens.last() != Some(&Token::Any) {
+                    tokens.push(Token::Any);
+                }
+            }
+            '?' => tokens.push(Token::One),
+            '\\' if index + 1 < chars.len() => {
+                index += 1;
+                tokens.push(Token::Literal(chars[index]));
+            }
+            '[' => {
+                match parse_class(&chars[index + 1..]) {
+                    Some((class, consumed)) => {
+                        tokens.push(class);
+                        index += consumed;
+                    }
+                    None => tokens.push(Token::Literal('[')),
+                }
+            }
+            literal => tokens.push(Token::Literal(literal)),
+        }
+        index += 1;
+    }
+    tokens
+}
+
+
+/// Parse a character class following its `[`. Returns the class and the
+/// number of characters it took, including the closing `]`, or `None` when
+/// the class is never closed.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    let mut index = usize::from(negated);
+    let mut ranges = Vec::new();
+    // a `]` right after the opening bracket is a member, not the end
+    let mut first = true;
+    while index < chars.len() {
+        let start = chars[index];
+        if start == ']' && !first {
+            return Some((
+                Token::Class {
+                    negated,
+                    ranges,
+                },
+                index + 1,
+            ));
+        }
+        first = false;
+        if chars.get(index + 1) == Some(&'-')
+            && let Some(&end) = chars.get(index + 2)
+            && end != ']'
+        {
+            ranges.push((start, end));
+            index += 3;
+        } else {
+            ranges.push((start, start));
+            index += 1;
+        }
+    }
+    None
+}
+
+
+/// Match path components against pattern segments:
+/// - `**` matches zero components (advance the pattern), or one component
+///   then retries `**` (advance the path);
+/// - a name segment matches exactly one component.
+fn segments_match(components: &[Vec<char>], segments: &[Segment]) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        Some((Segment::AnyComponents, rest)) => {
+            segments_match(components, rest)
+                || matches!(components.split_first(), Some((_, tail)) if segments_match(tail, segments))
+        }
+        Some((Segment::Name(tokens), rest)) => {
+            matches!(components.split_first(), Some((name, tail)) if tokens_match(name, tokens) && segments_match(tail, rest))
+        }
+    }
+}
+
+
+/// Match one path component against tokens, by recursive descent over the
+/// two slices:
+/// - `*` matches zero characters (advance the pattern), or one character then
+///   retries `*` (advance the name);
+/// - `?` and classes match exactly one character;
+/// - any other character matches itself.
+fn tokens_match(name: &[char], tokens: &[Token]) -> bool {
+    match tokens.split_first() {
+        None => name.is_empty(),
+        Some((Token::Any, rest)) => {
+            tokens_match(name, rest)
+                || matches!(name.split_first(), Some((_, tail)) if tokens_match(tail, tokens))
+        }
+        Some((Token::One, rest)) => {
+            matches!(name.split_first(), Some((_, tail)) if tokens_match(tail, rest))
+        }
+        Some((
+            Token::Class {
+                negated,
+                ranges,
+            },
+            rest,
+        )) => {
+            matches!(name.split_first(), Some((first, tail))
+                if ranges.iter().any(|(start, end)| (start..=end).contains(&first)) != *negated
+                    && tokens_match(tail, rest))
+        }
+        Some((Token::Literal(expected), rest)) => {
+            matches!(name.split_first(), Some((first, tail)) if first == expected && tokens_match(tail, rest))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Glob, SplitPath, check, expand_braces, matches_any, split_static_prefix};
+    use std::path::Path;
+
+    #[test]
+    fn check_reports_unbalanced_patterns() {
+        assert!(check("**/{cache,tmp}/[a-z]*.log").is_ok());
+        assert!(check(r"\[literal\]").is_ok());
+        assert!(check("").is_err());
+        assert!(check("*.[ch").is_err());
+        assert!(check("{a,b").is_err());
+        assert!(check("a,b}").is_err());
+        assert!(check(r"trailing\").is_err());
+    }
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Glob::new(pattern).matches(Path::new(path), false)
+    }
+
+    #[test]
+    fn unanchored_pattern_matches_any_component() {
+        assert!(matches("node_modules", "node_modules/left-pad/index.js"));
+        assert!(matches(
+            "node_modules",
+            "web/node_modules/left-pad/index.js"
+        ));
+        assert!(matches("*.tmp", "deep/down/file.tmp"));
+        assert!(!matches("node_modules", "web/node_modules_backup.js"));
+    }
+
+    #[test]
+    fn anchored_pattern_matches_from_root() {
+        assert!(matches("/target", "target/debug/lw"));
+        assert!(matches("logs/*.log", "logs/app.log"));
+        assert!(!matches("/target", "crates/target/debug/lw"));
+        assert!(!matches("logs/*.log", "old/logs/app.log"));
+        assert!(
+            !matches("logs/*.log", "logs/2024/app.log"),
+            "* stays in one component"
+        );
+    }
+
+    #[test]
+    fn double_star_spans_components() {
+        assert!(matches("**/cache/**", "cache/entry"));
+        assert!(matches("**/cache/**", "a/b/cache/c/d"));
+        assert!(matches("a/**/z.log", "a/z.log"));
+        assert!(matches("a/**/z.log", "a/b/c/z.log"));
+        assert!(!matches("**/cache/**", "caches/entry"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(matches("app.[0-9]", "app.7"));
+        assert!(matches("[abc].log", "b.log"));
+        assert!(!matches("[abc].log", "d.log"));
+        assert!(matches("[!abc].log", "d.log"));
+        assert!(!matches("[^abc].log", "a.log"));
+        assert!(matches("[]x].log", "].log"));
+        assert!(matches("[oops", "[oops"), "unclosed class is literal");
+    }
+
+    #[test]
+    fn braces_expand_to_alternatives() {
+        assert_eq!(expand_braces("*.{log,err}"), vec!["*.log", "*.err"]);
+        assert_eq!(expand_braces("{a,b{1,2}}.x"), vec!["a.x", "b1.x", "b2.x"]);
+        assert_eq!(expand_braces("{unclosed"), vec!["{unclosed"]);
+        assert!(matches("{target,build}/", "build/out"));
+        assert!(!matches("*.{log,err}", "app.out"));
+    }
+
+    #[test]
+    fn trailing_slash_matches_directories_only() {
+        let glob = Glob::new(".git/");
+        assert!(glob.matches(Path::new(".git"), true));
+        assert!(!glob.matches(Path::new(".git"), false));
+        assert!(glob.matches(Path::new(".git/HEAD"), false));
+    }
+
+    #[test]
+    fn exact_match_ignores_parent_directories() {
+        let glob = Glob::new("build");
+        assert!(glob.matches(Path::new("build/out.log"), false));
+        assert!(!glob.matches_exactly(Path::new("build/out.log"), false));
+        assert!(glob.matches_exactly(Path::new("sub/build"), true));
+    }
+
+    #[test]
+    fn static_prefix_becomes_the_root() {
+        assert_eq!(
+            split_static_prefix("/var/log/**/*.log"),
+            Some(("/var/log".to_string(), "/**/*.log".to_string()))
+        );
+        assert_eq!(
+            split_static_prefix("logs/app-*/current"),
+            Some(("logs".to_string(), "/app-*/current".to_string()))
+        );
+        assert_eq!(
+            split_static_prefix("*.log"),
+            Some((".".to_string(), "/*.log".to_string()))
+        );
+        assert_eq!(
+            split_static_prefix("/*.log"),
+            Some(("/".to_string(), "/*.log".to_string()))
+        );
+        assert_eq!(split_static_prefix("/var/log/syslog"), None);
+    }
+
+    #[test]
+    fn escaped_characters_are_literal() {
+        assert!(matches("\\*.log", "*.log"));
+        assert!(!matches("\\*.log", "app.log"));
+    }
+
+    #[test]
+    fn current_dir_components_are_skipped() {
+        assert!(matches("src/*.rs", "./src/main.rs"));
+        assert!(!matches("*", ""), "the root itself never matches");
+    }
+
+    #[test]
+    fn split_path_matches_like_the_path() {
+        let globs = ["*.tmp", "logs/", "src/**/*.rs"].map(Glob::new);
+        let path = Path::new("./src/bin/main.rs");
+        let split = SplitPath::new(path);
+        for glob in &globs {
+            assert_eq!(glob.matches_split(&split, false), glob.matches(path, false));
+        }
+        assert!(matches_any(&globs, path, false));
+        assert!(!matches_any(&globs[..2], path, false));
+    }
+}
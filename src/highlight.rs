@@ -1,33 +1,207 @@
 //! Syntax highlighting for watched-file output, backed by `syntect`.
 //!
 //! A single [`Highlighter`] owns the (expensive to build) syntax and theme
-//! sets and is created once via [`highlighter`]. The actual per-line
+//! sets and is shared via [`highlighter`], replaced only when it is
+//! [`init`]ialised again (on a configuration reload). The actual per-line
 //! highlighting in [`Highlighter::highlight`] is a pure transformation from
-//! `(extension, lines)` to ANSI-escaped lines.
+//! `(syntax, lines)` to ANSI-escaped lines, with the syntax of a file
+//! [`Highlighter::detect`]ed on its first read and cached with its state, as
+//! the index of the syntax in the highlighter's set.
+//!
+//! Custom `.sublime-syntax` and `.tmTheme` files from the configured
+//! directories are merged into syntect's bundled ones. Compiling syntaxes is
+//! slow, so the merged sets can be dumped to a cache file, which is used for
+//! as long as the files it was made from stay the same.
 
+use crate::config::Config;
 use crate::consts::DEFAULT_THEME;
-use std::sync::OnceLock;
+use colored::Colorize;
+use std::{
+    ffi::OsStr,
+    fs::create_dir_all,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::UNIX_EPOCH,
+};
+use syntect::dumps::{dump_to_file, from_dump_file};
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{Theme, ThemeSet};
-use syntect::parsing::SyntaxSet;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
 use syntect::util::as_24_bit_terminal_escaped;
+use walkdir::WalkDir;
 
 /// ANSI reset appended after each highlighted line so colors don't bleed.
 const ANSI_RESET: &str = "\x1b[0m";
 
 /// Process-wide highlighter, initialised on first use.
-static HIGHLIGHTER: OnceLock<Highlighter> = OnceLock::new();
+static HIGHLIGHTER: RwLock<Option<Arc<Highlighter>>> = RwLock::new(None);
 
-/// Initialise the shared highlighter with the given theme. Only the first call
-/// takes effect, so call this once at startup before any highlighting happens.
-pub fn init(theme_name: &str) {
-    let _ = HIGHLIGHTER.set(Highlighter::new(theme_name));
+/// Initialise the shared highlighter with the configured theme and custom
+/// syntaxes and themes, replacing the one in use. Call this at startup before
+/// any highlighting happens, and again whenever the configuration may have
+/// changed.
+pub fn init(config: &Config) {
+    let highlighter = Arc::new(Highlighter::load(config));
+    if let Ok(mut shared) = HIGHLIGHTER.write() {
+        *shared = Some(highlighter);
+    }
 }
 
 /// Return the shared [`Highlighter`], building it with the default theme if it
 /// has not been [`init`]ialised yet.
-pub fn highlighter() -> &'static Highlighter {
-    HIGHLIGHTER.get_or_init(|| Highlighter::new(DEFAULT_THEME))
+pub fn highlighter() -> Arc<Highlighter> {
+    if let Ok(shared) = HIGHLIGHTER.read()
+        && let Some(highlighter) = shared.as_ref()
+    {
+        return Arc::clone(highlighter);
+    }
+    let highlighter = Arc::new(Highlighter::new(DEFAULT_THEME));
+    if let Ok(mut shared) = HIGHLIGHTER.write() {
+        Arc::clone(shared.get_or_insert(highlighter))
+    } else {
+        highlighter
+    }
+}
+
+/// Problems loading the custom syntaxes and themes from `syntax_dirs` and
+/// `theme_dirs`, and a `theme_name` or `syntax_names` found neither among
+/// them nor the bundled ones, described (for `lw config check`).
+pub fn check(
+    theme_name: Option<&str>,
+    syntax_names: &[&str],
+    syntax_dirs: &[String],
+    theme_dirs: &[String],
+) -> Vec<String> {
+    let (syntaxes, themes, mut problems) = build_sets(syntax_dirs, theme_dirs);
+    problems.extend(
+        syntax_names
+            .iter()
+            .filter(|name| find_syntax(&syntaxes, name).is_none())
+            .map(|name| format!("Unknown syntax: {name}")),
+    );
+    if let Some(theme_name) = theme_name
+        && !themes.themes.contains_key(theme_name)
+    {
+        problems.push(format!("Unknown theme: {theme_name}"));
+    }
+    problems
+}
+
+/// The syntax called `syntax` in `syntaxes`, or else with `syntax` as one of
+/// its file extensions, or its name in any case.
+fn find_syntax<'a>(syntaxes: &'a SyntaxSet, syntax: &str) -> Option<&'a SyntaxReference> {
+    syntaxes
+        .find_syntax_by_name(syntax)
+        .or_else(|| syntaxes.find_syntax_by_token(syntax))
+}
+
+/// Syntax and theme sets: syntect's bundled ones, with the custom ones from
+/// `syntax_dirs` and `theme_dirs` merged in. They're read from the dump at
+/// `cache` when it was made from the same files, and else dumped there (unless
+/// some failed to load, so the problem is reported again next time).
+fn load_sets(
+    syntax_dirs: &[String],
+    theme_dirs: &[String],
+    cache: Option<&str>,
+) -> (SyntaxSet, ThemeSet) {
+    if syntax_dirs.is_empty() && theme_dirs.is_empty() {
+        return (
+            SyntaxSet::load_defaults_newlines(),
+            ThemeSet::load_defaults(),
+        );
+    }
+    let key = sources_key(syntax_dirs, theme_dirs);
+    if let Some(cache) = cache
+        && let Ok((cached_key, syntaxes, themes)) =
+            from_dump_file::<(String, SyntaxSet, ThemeSet), _>(cache)
+        && cached_key == key
+    {
+        debug!("Syntaxes and themes loaded from cache: {}", cache.cyan());
+        return (syntaxes, themes);
+    }
+    let (syntaxes, themes, problems) = build_sets(syntax_dirs, theme_dirs);
+    for problem in &problems {
+        warn!("{}", problem.red());
+    }
+    if let Some(cache) = cache
+        && problems.is_empty()
+    {
+        if let Some(directory) = Path::new(cache).parent() {
+            create_dir_all(directory).unwrap_or_default();
+        }
+        match dump_to_file(&(key, &syntaxes, &themes), cache) {
+            Ok(()) => debug!("Syntaxes and themes cached in: {}", cache.cyan()),
+            Err(error_cause) => {
+                warn!(
+                    "Couldn't write syntax cache: {}. Caused by: {}",
+                    cache.cyan(),
+                    error_cause.to_string().red()
+                )
+            }
+        }
+    }
+    (syntaxes, themes)
+}
+
+/// Build the syntax and theme sets of [`load_sets`] from the files, with the
+/// problems loading them, described.
+fn build_sets(
+    syntax_dirs: &[String],
+    theme_dirs: &[String],
+) -> (SyntaxSet, ThemeSet, Vec<String>) {
+    let mut problems = Vec::new();
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    for directory in syntax_dirs {
+        if let Err(error_cause) = builder.add_from_folder(directory, true) {
+            problems.push(format!(
+                "Couldn't load syntaxes from: {directory}. Caused by: {error_cause}"
+            ));
+        }
+    }
+    let mut themes = ThemeSet::load_defaults();
+    for directory in theme_dirs {
+        if let Err(error_cause) = themes.add_from_folder(directory) {
+            problems.push(format!(
+                "Couldn't load themes from: {directory}. Caused by: {error_cause}"
+            ));
+        }
+    }
+    (builder.build(), themes, problems)
+}
+
+/// Identifies the files the sets of [`load_sets`] are made from: by the `lw`
+/// version (which pins the syntect one, and with it the dump format) and the
+/// path, size and modification time of every file in the directories.
+fn sources_key(syntax_dirs: &[String], theme_dirs: &[String]) -> String {
+    let mut key = String::from(env!("CARGO_PKG_VERSION"));
+    let directories = syntax_dirs
+        .iter()
+        .map(|directory| ("syntaxes", directory))
+        .chain(theme_dirs.iter().map(|directory| ("themes", directory)));
+    for (kind, directory) in directories {
+        key.push_str(&format!("\n{kind}: {directory}"));
+        for entry in WalkDir::new(directory)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+        {
+            if let Ok(metadata) = entry.metadata() {
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+                key.push_str(&format!(
+                    "\n{} {} {}",
+                    entry.path().display(),
+                    metadata.len(),
+                    modified.as_nanos()
+                ));
+            }
+        }
+    }
+    key
 }
 
 
@@ -42,8 +216,33 @@ impl Highlighter {
     /// Build a highlighter from syntect's bundled defaults, using `theme_name`
     /// (falling back to a known-present theme if it is missing).
     pub fn new(theme_name: &str) -> Self {
-        let syntaxes = SyntaxSet::load_defaults_newlines();
-        let mut themes = ThemeSet::load_defaults().themes;
+        Self::with_sets(
+            theme_name,
+            SyntaxSet::load_defaults_newlines(),
+            ThemeSet::load_defaults(),
+        )
+    }
+
+    /// Build a highlighter as configured: the bundled defaults with the custom
+    /// syntaxes and themes of [`Config::syntax_dirs`] and
+    /// [`Config::theme_dirs`], using [`Config::theme`].
+    pub fn load(config: &Config) -> Self {
+        let (syntaxes, themes) = load_sets(
+            config.syntax_dirs.as_deref().unwrap_or_default(),
+            config.theme_dirs.as_deref().unwrap_or_default(),
+            config.syntax_cache.as_deref(),
+        );
+        Self::with_sets(
+            config.theme.as_deref().unwrap_or(DEFAULT_THEME),
+            syntaxes,
+            themes,
+        )
+    }
+
+    /// Build a highlighter from the given sets, using `theme_name` like
+    /// [`Highlighter::new`].
+    fn with_sets(theme_name: &str, syntaxes: SyntaxSet, themes: ThemeSet) -> Self {
+        let mut themes = themes.themes;
         let theme = themes
             .remove(theme_name)
             .or_else(|| themes.remove("base16-ocean.dark"))
@@ -55,23 +254,67 @@ impl Highlighter {
         }
     }
 
-    /// Highlight `lines` as source of the language matching `extension`,
-    /// returning ANSI-escaped strings. Unknown extensions fall back to plain
-    /// text, and any per-line highlighting error yields the original line.
-    pub fn highlight(&self, extension: &str, lines: &[String]) -> Vec<String> {
-        let syntax = self
-            .syntaxes
-            .find_syntax_by_extension(extension)
+    /// Index of the syntax called `syntax` (or with `syntax` as extension), if
+    /// any, for [`Highlighter::highlight`].
+    pub fn find(&self, syntax: &str) -> Option<usize> {
+        find_syntax(&self.syntaxes, syntax).and_then(|syntax| self.index_of(syntax))
+    }
+
+    /// Index of the syntax of the file at `file_path`: the `explicit` one (a
+    /// syntax name or extension), else the one for its full file name (like
+    /// `Makefile`), its extension, or its first line (a shebang, `<?xml`,
+    /// ...) as read by `first_line`. `None` when none matches.
+    pub fn detect(
+        &self,
+        file_path: &Path,
+        explicit: Option<&str>,
+        first_line: impl FnOnce() -> Option<String>,
+    ) -> Option<usize> {
+        let by_extension = |name: Option<&OsStr>| {
+            name.and_then(|name| name.to_str())
+                .and_then(|name| self.syntaxes.find_syntax_by_extension(name))
+        };
+        explicit
+            .and_then(|syntax| find_syntax(&self.syntaxes, syntax))
+            .or_else(|| by_extension(file_path.file_name()))
+            .or_else(|| by_extension(file_path.extension()))
+            .or_else(|| {
+                first_line().and_then(|line| self.syntaxes.find_syntax_by_first_line(&line))
+            })
+            .and_then(|syntax| self.index_of(syntax))
+    }
+
+    /// Index of `syntax`, one of this highlighter's syntaxes.
+    fn index_of(&self, syntax: &SyntaxReference) -> Option<usize> {
+        self.syntaxes
+            .syntaxes()
+            .iter()
+            .position(|candidate| std::ptr::eq(candidate, syntax))
+    }
+
+    /// Highlight `lines` as source of the language of the `syntax` index (see
+    /// [`Highlighter::find`]), returning ANSI-escaped strings. No (or an
+    /// unknown) syntax falls back to plain text, and any per-line
+    /// highlighting error yields the original line.
+    pub fn highlight(&self, syntax: Option<usize>, lines: &[String]) -> Vec<String> {
+        let syntax = syntax
+            .and_then(|index| self.syntaxes.syntaxes().get(index))
             .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
         let mut highlighter = HighlightLines::new(syntax, &self.theme);
+        // the syntaxes expect lines with their newline, which ends line
+        // comments and the like; one buffer holds each line with it
+        let mut line_with_newline = String::new();
         lines
             .iter()
             .map(|line| {
-                match highlighter.highlight_line(line, &self.syntaxes) {
+                line_with_newline.clear();
+                line_with_newline.push_str(line);
+                line_with_newline.push('\n');
+                match highlighter.highlight_line(&line_with_newline, &self.syntaxes) {
                     Ok(ranges) => {
                         format!(
                             "{}{}",
-                            as_24_bit_terminal_escaped(&ranges, false),
+                            as_24_bit_terminal_escaped(&ranges, false).trim_end_matches('\n'),
                             ANSI_RESET
                         )
                     }
@@ -85,12 +328,13 @@ impl Highlighter {
 
 #[cfg(test)]
 mod tests {
-    use super::Highlighter;
+    use super::{Highlighter, check, load_sets};
+    use std::{fs, path::Path};
 
     #[test]
     fn known_extension_emits_ansi_color_codes() {
         let highlighter = Highlighter::new("base16-ocean.dark");
-        let out = highlighter.highlight("rs", &["fn main() {}".to_string()]);
+        let out = highlighter.highlight(highlighter.find("rs"), &["fn main() {}".to_string()]);
         assert_eq!(out.len(), 1);
         // Rust source should be colored -> contains ANSI escape sequences.
         assert!(
@@ -106,8 +350,10 @@ mod tests {
         let highlighter = Highlighter::new("base16-ocean.dark");
         // Plain text still round-trips the content (possibly with color codes),
         // and must preserve the original text.
-        let out =
-            highlighter.highlight("this-ext-does-not-exist", &["hello world".to_string()]);
+        let out = highlighter.highlight(
+            highlighter.find("this-ext-does-not-exist"),
+            &["hello world".to_string()],
+        );
         assert_eq!(out.len(), 1);
         assert!(out[0].contains("hello world"));
     }
@@ -116,6 +362,101 @@ mod tests {
     fn line_count_is_preserved() {
         let highlighter = Highlighter::new("base16-ocean.dark");
         let lines = vec!["let x = 1;".to_string(), "let y = 2;".to_string()];
-        assert_eq!(highlighter.highlight("rs", &lines).len(), 2);
+        assert_eq!(
+            highlighter.highlight(highlighter.find("rs"), &lines).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn custom_syntaxes_and_themes_are_merged_and_cached() {
+        let root =
+            std::env::temp_dir().join(format!("lw_highlight_test_{}", std::process::id()));
+        let (syntax_dir, theme_dir) = (root.join("syntaxes"), root.join("themes"));
+        fs::create_dir_all(&syntax_dir).unwrap();
+        fs::create_dir_all(&theme_dir).unwrap();
+        fs::write(
+            syntax_dir.join("dsl.sublime-syntax"),
+            "%YAML 1.2\n---\nname: Dsl\nfile_extensions: [dsl]\nscope: source.dsl\n\
+             contexts:\n  main:\n    - match: '\\bwhen\\b'\n      scope: keyword.dsl\n",
+        )
+        .unwrap();
+        fs::write(
+            theme_dir.join("company.tmTheme"),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plist version=\"1.0\"><dict>\
+             <key>name</key><string>Company</string><key>settings</key><array><dict>\
+             <key>settings</key><dict><key>foreground</key><string>#FF0000</string>\
+             </dict></dict></array></dict></plist>\n",
+        )
+        .unwrap();
+        let syntax_dirs = [syntax_dir.to_string_lossy().to_string()];
+        let theme_dirs = [theme_dir.to_string_lossy().to_string()];
+        let cache = root
+            .join("cache/syntaxes.bin")
+            .to_string_lossy()
+            .to_string();
+
+        for _ in 0..2 {
+            let (syntaxes, themes) = load_sets(&syntax_dirs, &theme_dirs, Some(&cache));
+            assert!(syntaxes.find_syntax_by_extension("dsl").is_some());
+            assert!(syntaxes.find_syntax_by_extension("rs").is_some());
+            assert!(themes.themes.contains_key("company"));
+            assert!(fs::metadata(&cache).is_ok());
+        }
+        assert!(check(Some("company"), &["Dsl"], &syntax_dirs, &theme_dirs).is_empty());
+        assert_eq!(
+            check(
+                Some("company"),
+                &["Dsl"],
+                &[],
+                &["/nonexistent/lw".to_string()]
+            )
+            .len(),
+            3
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn syntax_is_detected_by_name_extension_and_first_line() {
+        let highlighter = Highlighter::new("base16-ocean.dark");
+        let detect = |path: &str, explicit: Option<&str>, first_line: &str| {
+            highlighter
+                .detect(Path::new(path), explicit, || Some(first_line.to_string()))
+                .map(|index| highlighter.syntaxes.syntaxes()[index].name.clone())
+        };
+        assert_eq!(
+            detect("/src/Makefile", None, "").as_deref(),
+            Some("Makefile")
+        );
+        assert_eq!(detect("/src/main.rs", None, "").as_deref(), Some("Rust"));
+        assert_eq!(
+            detect("/bin/deploy", None, "#!/usr/bin/env python3").as_deref(),
+            Some("Python")
+        );
+        assert_eq!(
+            detect("/var/log/app.conf", Some("json"), "").as_deref(),
+            Some("JSON")
+        );
+        assert_eq!(
+            detect("/var/log/app.conf", Some("Rust"), "").as_deref(),
+            Some("Rust")
+        );
+        assert_eq!(detect("/var/log/app", None, "started"), None);
+    }
+
+    #[test]
+    fn line_comments_end_with_their_line() {
+        let highlighter = Highlighter::new("base16-ocean.dark");
+        let lines = ["# comment".to_string(), "echo hi".to_string()];
+        let out = highlighter.highlight(highlighter.find("sh"), &lines);
+        let comment = highlighter.highlight(highlighter.find("sh"), &lines[..1]);
+        let comment_color = &comment[0][..=comment[0].find('m').unwrap()];
+        assert!(!out[1].contains('\n'));
+        assert!(
+            !out[1].starts_with(comment_color),
+            "code after a comment line isn't colored as comment: {:?}",
+            out[1]
+        );
     }
 }
@@ -0,0 +1,190 @@
+//! `.gitignore`, `.ignore` and `.lwignore` support.
+//!
+//! Every directory of a watched tree may hold ignore files. Their rules apply
+//! to the directory and everything below it, relative to the directory: rules
+//! of deeper directories come after the ones of their parents, and within a
+//! directory `.ignore` comes after `.gitignore` and `.lwignore` after both.
+//! The last matching rule decides, so a `!negated` rule re-includes what an
+//! earlier one ignored, except below an ignored directory (like git does).
+//!
+//! Parsed rules are cached per directory in [`IgnoreFiles`], and read again
+//! with [`IgnoreFiles::reload`] when the directory or one of its ignore files
+//! changes.
+
+use crate::consts::IGNORE_FILE_NAMES;
+use crate::glob::Glob;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+
+/// A single line of an ignore file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    glob: Glob,
+
+    /// Line started with `!`: re-include what matches.
+    negated: bool,
+}
+
+
+/// The rules of the ignore files of every directory looked at so far. Held by
+/// the configuration, so a reload starts over with nothing cached.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreFiles {
+    rules: RefCell<HashMap<PathBuf, Rc<Vec<Rule>>>>,
+}
+
+
+/// Whether `file_name` is one of the ignore files.
+pub fn is_ignore_file(file_name: &Path) -> bool {
+    file_name
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| IGNORE_FILE_NAMES.contains(&name))
+}
+
+
+impl IgnoreFiles {
+    /// Read the ignore files of `directory` again. Returns whether its rules
+    /// changed, so the paths below it may be ignored differently now.
+    pub fn reload(&self, directory: &Path) -> bool {
+        let rules = Rc::new(read_rules(directory));
+        let previous = self
+            .rules
+            .borrow_mut()
+            .insert(directory.to_path_buf(), Rc::clone(&rules));
+        previous != Some(rules)
+    }
+
+
+    /// Whether `path`, below the watched `root`, is ignored by the ignore files
+    /// of the directories from `root` down to it. A path below an ignored
+    /// directory is ignored too.
+    pub fn is_ignored(&self, root: &Path, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative_path) = path.strip_prefix(root) else {
+            return false;
+        };
+        let components: Vec<_> = relative_path.components().collect();
+        let mut prefix = root.to_path_buf();
+        components.iter().enumerate().any(|(index, component)| {
+            prefix.push(component);
+            self.is_ignored_itself(root, &prefix, index + 1 < components.len() || is_dir)
+        })
+    }
+
+
+    /// Whether the last rule matching `path` itself ignores it.
+    fn is_ignored_itself(&self, root: &Path, path: &Path, is_dir: bool) -> bool {
+        let directories: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|directory| directory.starts_with(root))
+            .collect();
+        let mut ignored = false;
+        for directory in directories.into_iter().rev() {
+            let Ok(relative_path) = path.strip_prefix(directory) else {
+                continue;
+            };
+            for rule in self.rules_of(directory).iter() {
+                if rule.glob.matches_exactly(relative_path, is_dir) {
+                    ignored = !rule.negated;
+                }
+            }
+        }
+        ignored
+    }
+
+
+    /// Rules of the ignore files in `directory`, read on first use.
+    fn rules_of(&self, directory: &Path) -> Rc<Vec<Rule>> {
+        Rc::clone(
+            self.rules
+                .borrow_mut()
+                .entry(directory.to_path_buf())
+                .or_insert_with(|| Rc::new(read_rules(directory))),
+        )
+    }
+}
+
+
+/// Rules of the ignore files in `directory`, in increasing precedence.
+fn read_rules(directory: &Path) -> Vec<Rule> {
+    IGNORE_FILE_NAMES
+        .iter()
+        .filter_map(|name| read_to_string(directory.join(name)).ok())
+        .flat_map(|contents| parse(&contents))
+        .collect()
+}
+
+
+/// Parse the contents of an ignore file: one pattern per line, blank lines and
+/// `#` comments skipped, `!` negating, `\` escaping a leading `#` or `!`.
+fn parse(contents: &str) -> Vec<Rule> {
+    contents
+        .lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, line),
+            };
+            Rule {
+                glob: Glob::new(pattern),
+                negated,
+            }
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{IgnoreFiles, is_ignore_file, parse};
+    use std::{fs, path::Path};
+
+    #[test]
+    fn comments_blank_lines_and_negations() {
+        let rules = parse("# build output\n\ntarget/\n!keep.log\n\\#literal\n");
+        assert_eq!(rules.len(), 3);
+        assert!(!rules[0].negated);
+        assert!(rules[1].negated);
+        assert!(rules[2].glob.matches_exactly(Path::new("#literal"), false));
+    }
+
+    #[test]
+    fn ignore_file_names() {
+        assert!(is_ignore_file(Path::new("project/.gitignore")));
+        assert!(is_ignore_file(Path::new(".lwignore")));
+        assert!(!is_ignore_file(Path::new("gitignore.txt")));
+    }
+
+    #[test]
+    fn rules_apply_hierarchically_with_negations() {
+        let root = std::env::temp_dir().join(format!("lw_ignore_test_{}", std::process::id()));
+        fs::create_dir_all(root.join("app/target")).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\ntarget/\n").unwrap();
+        fs::write(root.join("app/.lwignore"), "!important.log\n").unwrap();
+        let ignore_files = IgnoreFiles::default();
+        assert!(ignore_files.is_ignored(&root, &root.join("debug.log"), false));
+        assert!(ignore_files.is_ignored(&root, &root.join("app/debug.log"), false));
+        assert!(!ignore_files.is_ignored(&root, &root.join("app/important.log"), false));
+        assert!(ignore_files.is_ignored(&root, &root.join("important.log"), false));
+        assert!(ignore_files.is_ignored(&root, &root.join("app/target"), true));
+        assert!(ignore_files.is_ignored(&root, &root.join("app/target/important.log"), false));
+        assert!(!ignore_files.is_ignored(&root, &root.join("app/main.rs"), false));
+
+        // changed rules are only seen once the directory is reloaded
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        assert!(ignore_files.is_ignored(&root, &root.join("debug.log"), false));
+        assert!(ignore_files.reload(&root));
+        assert!(!ignore_files.is_ignored(&root, &root.join("debug.log"), false));
+        assert!(!ignore_files.reload(&root), "nothing changed since");
+        let _ = fs::remove_dir_all(&root);
+    }
+}
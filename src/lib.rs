@@ -33,8 +33,18 @@
 #[macro_use]
 extern crate log;
 
+pub mod binary;
+pub mod cli;
+pub mod compression;
 pub mod config;
 pub mod consts;
+pub mod debounce;
+pub mod encoding;
+pub mod glob;
 pub mod highlight;
+pub mod ignore_files;
+pub mod rules;
+pub mod stream;
 pub mod types;
 pub mod utils;
+pub mod watcher;
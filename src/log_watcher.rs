@@ -25,32 +25,75 @@ extern crate log;
 use chrono::Local;
 use colored::Colorize;
 use fern::Dispatch;
-use kqueue2::{Ident::*, Watcher};
-use lw::config::Config;
-use lw::consts::DEFAULT_THEME;
+use kqueue2::Ident::*;
+use lw::cli::{Cli, ConfigCommand, USAGE};
+use lw::config::{Config, EnvironmentProblem, LoadProblem};
+use lw::consts::{
+    EVICTED_POLL_INTERVAL_MS, HOUSEKEEPING_INTERVAL_MS, STREAM_POLL_INTERVAL_MS,
+};
+use lw::debounce::Debouncer;
+use lw::stream::{Streams, is_stream_source};
 use lw::types::FileAndPosition;
-use lw::utils::{process_file_event, walkdir_recursive, watch_the_watcher};
-use std::{env, fs::OpenOptions, path::Path, process::exit, thread, time::Duration};
+use lw::utils::{
+    drain_watched_files, poll_evicted_files, print_stream_chunk, print_tails, process_exists,
+    process_file_event, rewatch_with_config, walkdir_recursive, watch_config_file, watch_pid,
+    watch_the_watcher,
+};
+use lw::watcher::KqueueWatcher;
+use signal_hook::consts::{SIGHUP, SIGUSR1};
+use std::{
+    env,
+    fs::OpenOptions,
+    path::Path,
+    process::exit,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 
 fn main() {
-    // Note whether a config existed *before* loading, since load() creates a
-    // default one when missing. We report it once the logger is initialised.
-    let wrote_default_config = Config::existing_config_path().is_none();
-    let config = Config::load();
+    // read options and paths given as arguments (first arg is $0):
+    let cli = Cli::parse(env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{}: {error}\n\n{USAGE}", "ERROR".red());
+        exit(1)
+    });
+    if cli.help {
+        println!("{USAGE}");
+        exit(0)
+    }
+    if let Some(config_command) = &cli.config_command {
+        run_config_command(config_command, &cli)
+    }
+
+    let mut config =
+        Config::load(cli.profile.as_deref(), |config| cli.override_config(config));
+    let mut config_paths = Config::existing_config_paths();
+    // paths given as arguments, or else the ones of the profile:
+    let paths = if cli.paths.is_empty() {
+        config.profile_paths().to_vec()
+    } else {
+        cli.paths.clone()
+    };
+    // stdin, pipes and devices are read as streams, the rest is watched:
+    let (stream_paths, path_arguments): (Vec<String>, Vec<String>) = paths
+        .iter()
+        .cloned()
+        .partition(|a_path| is_stream_source(a_path));
+    // glob arguments are watched from their static prefix:
+    config.set_roots(&path_arguments);
+    let paths_to_watch = config.root_paths();
     let log_level = config.get_log_level();
     let output = config.output.clone().unwrap_or_default();
 
-    // read paths given as arguments:
-    let paths_to_watch: Vec<String> = env::args()
-        .skip(1) // first arg is $0
-        .collect();
-
     // mutable hashmap keeping position of all watched files:
     let mut watched_file_states = FileAndPosition::new();
 
     // mutable kqueue watcher:
-    let mut kqueue_watcher = Watcher::new().expect("Could not create kqueue watcher!");
+    let mut kqueue_watcher = KqueueWatcher::new(config.max_open_files.unwrap_or_default())
+        .expect("Could not create kqueue watcher!");
 
     // name of the last logged file:
     let mut last_file = String::new();
@@ -77,56 +120,330 @@ fn main() {
         .apply()
         .expect("Couldn't initialize Fern logger!");
 
-    if wrote_default_config {
-        info!(
-            "No configuration file found — wrote defaults to: {}",
-            Config::default_config_path().cyan()
-        );
+    if config_paths.is_empty() {
+        debug!("No configuration file found, using defaults");
     }
 
-    // Initialise syntax highlighting with the configured theme.
-    lw::highlight::init(config.theme.as_deref().unwrap_or(DEFAULT_THEME));
+    // Initialise syntax highlighting with the configured theme and syntaxes.
+    lw::highlight::init(&config);
 
     debug!("Watching paths: {}", paths_to_watch.join(", "));
-    if paths_to_watch.is_empty() {
+    if paths.is_empty() && cli.command.is_empty() {
         error!("FATAL ERROR: {}", "No paths specified as arguments! You have to specify at least a single directory/file to watch!".red());
         exit(1)
     }
 
     // initial watches for specified dirs/files:
-    paths_to_watch.into_iter().for_each(|a_path| {
+    paths_to_watch.iter().for_each(|a_path| {
         // Handle case when given a file as argument
         walkdir_recursive(
             &mut kqueue_watcher,
             &mut watched_file_states,
             &mut last_file,
-            Path::new(&a_path),
+            Path::new(a_path),
             &config,
+            true,
         );
     });
 
+    // one-shot mode: show what we have and leave
+    if cli.once {
+        print_tails(&watched_file_states, &mut last_file, &config, cli.sort, 0);
+        exit(0)
+    }
+
+    // show where existing files are at before following them
+    if config.initial_tails.unwrap_or_default() {
+        print_tails(
+            &watched_file_states,
+            &mut last_file,
+            &config,
+            cli.sort,
+            config.initial_tail_files.unwrap_or_default(),
+        );
+    }
+
+    // stream sources and the command run alongside the watched paths:
+    let mut streams = Streams::default();
+    stream_paths
+        .iter()
+        .for_each(|stream_path| streams.add_path(stream_path));
+    let only_streams = paths_to_watch.is_empty() && cli.command.is_empty();
+    if !cli.command.is_empty() {
+        streams
+            .spawn_command(&cli.command)
+            .unwrap_or_else(|error_cause| {
+                error!(
+                    "FATAL ERROR: Couldn't run command: {}. Caused by: {}",
+                    cli.command.join(" ").cyan(),
+                    error_cause.to_string().red()
+                );
+                exit(1)
+            });
+    }
+
+    // processes we outlive; the ones already gone can't be watched anymore:
+    let mut watched_pids: Vec<i32> = cli
+        .pids
+        .iter()
+        .copied()
+        .filter(|&pid| watch_pid(&mut kqueue_watcher, pid))
+        .collect();
+    let following_pids = !cli.pids.is_empty();
+
+    // events are coalesced per path, each path handled once its window closes:
+    let mut debouncer = Debouncer::new(Duration::from_millis(
+        config.debounce_ms.unwrap_or_default(),
+    ));
+
+    // SIGUSR1 asks for a dump of the watch budget usage:
+    let status_requested = Arc::new(AtomicBool::new(false));
+    if let Err(error_cause) =
+        signal_hook::flag::register(SIGUSR1, Arc::clone(&status_requested))
+    {
+        warn!("Couldn't handle SIGUSR1: {}", error_cause.to_string().red());
+    }
+    debug!("{}", kqueue_watcher.status());
+
+    // changes of the configuration file, or SIGHUP, reload the configuration:
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    if let Err(error_cause) =
+        signal_hook::flag::register(SIGHUP, Arc::clone(&reload_requested))
+    {
+        warn!("Couldn't handle SIGHUP: {}", error_cause.to_string().red());
+    }
+    config_paths
+        .iter()
+        .for_each(|config_path| watch_config_file(&mut kqueue_watcher, config_path));
+    let mut evicted_polled_at = Instant::now();
+
     // handle events dynamically, including new files
     loop {
         watch_the_watcher(&mut kqueue_watcher);
-        while let Some(an_event) = kqueue_watcher.iter().next() {
-            debug!("Watched files: {}", watched_file_states.len());
+
+        // block until the first event arrives, a debounce window closes or
+        // housekeeping is due, then take whatever else is queued without waiting.
+        // Stream lines don't wake kqueue, so poll for them more often.
+        let max_wait = if streams.all_closed() {
+            Duration::from_millis(HOUSEKEEPING_INTERVAL_MS)
+        } else {
+            Duration::from_millis(STREAM_POLL_INTERVAL_MS)
+        };
+        let mut timeout = Some(debouncer.timeout(Instant::now(), max_wait));
+        while let Some(an_event) = kqueue_watcher.poll(timeout) {
+            timeout = None;
             match an_event.ident {
                 Filename(_file_descriptor, abs_file_name) => {
-                    process_file_event(
-                        &abs_file_name,
-                        &mut kqueue_watcher,
-                        &mut watched_file_states,
-                        &mut last_file,
-                        &config,
-                    );
-                    watch_the_watcher(&mut kqueue_watcher);
+                    kqueue_watcher.touch(&abs_file_name);
+                    debouncer.add(&abs_file_name, Instant::now());
+                }
+
+                Pid(pid) => {
+                    debug!("Watched process exited: {}", format!("{pid}").cyan());
+                    kqueue_watcher.remove_pid(pid);
+                    watched_pids.retain(|&watched_pid| watched_pid != pid);
                 }
 
                 event => warn!("Unknown event: {}", format!("{event:?}").cyan()),
             }
         }
 
-        // throttle 100ms
-        thread::sleep(Duration::from_millis(100));
+        for abs_file_name in debouncer.take_due(Instant::now()) {
+            if config_paths.contains(&abs_file_name) {
+                reload_requested.store(true, Ordering::Relaxed);
+                continue;
+            }
+            debug!(
+                "Watched files: {}, {}",
+                watched_file_states.len(),
+                kqueue_watcher.status()
+            );
+            process_file_event(
+                &abs_file_name,
+                &mut kqueue_watcher,
+                &mut watched_file_states,
+                &mut last_file,
+                &config,
+            );
+        }
+
+        // files that didn't fit into the watch budget are polled instead
+        if evicted_polled_at.elapsed() >= Duration::from_millis(EVICTED_POLL_INTERVAL_MS) {
+            evicted_polled_at = Instant::now();
+            poll_evicted_files(
+                &mut kqueue_watcher,
+                &mut watched_file_states,
+                &mut last_file,
+                &config,
+            );
+        }
+
+        // a broken configuration is rejected, the current one stays in use;
+        // positions of watched files survive either way. The watched paths
+        // stay the ones lw started with, even if the profile's `paths` changed
+        if reload_requested.swap(false, Ordering::Relaxed) {
+            let (mut reloaded, problems) =
+                Config::load_with_problems(cli.profile.as_deref(), |config| {
+                    cli.override_config(config)
+                });
+            let broken_files = problems.iter().find_map(|problem| {
+                match problem {
+                    LoadProblem::Files(error_cause) => Some(error_cause),
+                    _ => None,
+                }
+            });
+            if let Some(error_cause) = broken_files {
+                error!(
+                    "Configuration error: {}. Keeping the current configuration.",
+                    error_cause.to_string().red()
+                );
+            } else {
+                log_reload_problems(problems);
+                reloaded.set_roots(&path_arguments);
+                config = reloaded;
+                lw::highlight::init(&config);
+                debouncer.set_window(Duration::from_millis(
+                    config.debounce_ms.unwrap_or_default(),
+                ));
+                rewatch_with_config(
+                    &mut kqueue_watcher,
+                    &mut watched_file_states,
+                    &mut last_file,
+                    &config,
+                );
+                config_paths = Config::existing_config_paths();
+                info!(
+                    "Configuration reloaded from: {}",
+                    config_paths.join(", ").cyan()
+                );
+            }
+            config_paths
+                .iter()
+                .for_each(|config_path| watch_config_file(&mut kqueue_watcher, config_path));
+        }
+
+        if status_requested.swap(false, Ordering::Relaxed) {
+            info!("Status: {}", kqueue_watcher.status());
+        }
+
+        for chunk in streams.read_chunks() {
+            print_stream_chunk(chunk, &mut last_file);
+        }
+
+        // nothing but streams to follow, and all of them ended
+        if only_streams && streams.all_closed() {
+            exit(0)
+        }
+
+        // the command is done and its output shown: exit with its exit code
+        if let Some(exit_code) = streams.command_exit_code() {
+            drain_watched_files(
+                &mut kqueue_watcher,
+                &mut watched_file_states,
+                &mut last_file,
+                &config,
+            );
+            exit(exit_code)
+        }
+
+        // all processes we outlive are gone: print what they left behind and exit
+        watched_pids.retain(|&pid| process_exists(pid));
+        if following_pids && watched_pids.is_empty() {
+            drain_watched_files(
+                &mut kqueue_watcher,
+                &mut watched_file_states,
+                &mut last_file,
+                &config,
+            );
+            exit(0)
+        }
+    }
+}
+
+
+/// Log the `problems` found reloading the configuration, which is used
+/// without the parts they left out.
+fn log_reload_problems(problems: Vec<LoadProblem>) {
+    for problem in problems {
+        match problem {
+            LoadProblem::MissingFile(path) => {
+                error!("Configuration file: {} doesn't exist.", path.cyan())
+            }
+            LoadProblem::Files(error_cause) => {
+                error!("Configuration error: {}.", error_cause.to_string().red())
+            }
+            LoadProblem::Profile(error_cause) => {
+                error!(
+                    "Profile error: {}. Using the configuration files without it.",
+                    error_cause.to_string().red()
+                )
+            }
+            LoadProblem::Environment(EnvironmentProblem::Unknown(problem)) => {
+                warn!("Environment: {}", problem.yellow())
+            }
+            LoadProblem::Environment(EnvironmentProblem::Invalid(problem)) => {
+                error!("Environment: {}", problem.red())
+            }
+        }
+    }
+}
+
+
+/// Run a `lw config` subcommand, with the options of the `cli`, and exit.
+fn run_config_command(config_command: &ConfigCommand, cli: &Cli) -> ! {
+    match config_command {
+        ConfigCommand::Show {
+            origins,
+        } => {
+            let config =
+                Config::load(cli.profile.as_deref(), |config| cli.override_config(config))
+                    .resolved();
+            if *origins {
+                println!("{}", config.to_ron_with_origins().trim_end());
+            } else {
+                println!("{}", config.to_ron());
+            }
+            exit(0)
+        }
+        ConfigCommand::Default => {
+            println!("{}", Config::default().to_ron());
+            exit(0)
+        }
+        ConfigCommand::Init(path) => {
+            let path = path.clone().unwrap_or_else(Config::default_config_path);
+            if let Err(error_cause) = Config::init(&path) {
+                eprintln!(
+                    "{}: Couldn't write configuration to: {}. Caused by: {}",
+                    "ERROR".red(),
+                    path.cyan(),
+                    error_cause.to_string().red()
+                );
+                exit(1)
+            }
+            println!("Configuration template written to: {}", path.cyan());
+            exit(0)
+        }
+        ConfigCommand::Check(path) => {
+            let paths = match path {
+                Some(path) => vec![path.clone()],
+                None => Config::existing_config_paths(),
+            };
+            if paths.is_empty() {
+                eprintln!("{}: No configuration file found!", "ERROR".red());
+                exit(1)
+            }
+            let mut valid = true;
+            for path in &paths {
+                let problems = Config::check(path);
+                if problems.is_empty() {
+                    println!("{}: {}", path.cyan(), "OK".green());
+                }
+                for problem in &problems {
+                    eprintln!("{}: {}: {problem}", "ERROR".red(), path.cyan());
+                }
+                valid &= problems.is_empty();
+            }
+            exit(if valid { 0 } else { 1 })
+        }
     }
 }
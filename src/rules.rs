@@ -0,0 +1,261 @@
+//! Per-path rules: settings overridden for the files matching a glob.
+//!
+//! The configured [`Rule`]s are resolved into the [`FileRules`] of a file once,
+//! when it is first registered, and cached with its state (they're resolved
+//! again only on a configuration reload). Every matching rule applies, in the
+//! order configured, so a later rule overrides what an earlier one set.
+
+use crate::encoding::for_label;
+use colored::{Color, ColoredString, Colorize};
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
+
+
+/// Settings overridden for the files matching [`Self::pattern`]. Unset ones
+/// keep their value from the configuration (or from earlier rules).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    /// Glob pattern of the files the rule applies to, matched like
+    /// [`crate::config::Config::hexdump_patterns`].
+    pub pattern: String,
+
+    /// Show the files at all?
+    pub show: Option<bool>,
+
+    /// Highlight the files as this syntax, given by its name or file extension
+    /// (e.g. "JSON", "sh"), instead of the one detected.
+    pub syntax: Option<String>,
+
+    /// Text encoding of the files (a WHATWG label like "latin1").
+    pub encoding: Option<String>,
+
+    /// Bytes of tail shown when the files are newly watched.
+    pub tail_bytes: Option<u64>,
+
+    /// Lines of tail shown at startup and in one-shot mode.
+    pub tail_lines: Option<usize>,
+
+    /// Show only the lines containing one of these.
+    pub show_lines: Option<Vec<String>>,
+
+    /// Hide the lines containing one of these.
+    pub hide_lines: Option<Vec<String>>,
+
+    /// Name shown in the header of the files instead of their path.
+    pub label: Option<String>,
+
+    /// Color of the header of the files (e.g. "green", "bright magenta").
+    pub color: Option<String>,
+}
+
+
+impl Rule {
+    /// Values of the rule that mean nothing to `lw`, described. Its pattern is
+    /// checked by [`crate::config::Config::check`] with the other globs.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(label) = self.encoding.as_deref()
+            && for_label(label).is_none()
+        {
+            problems.push(format!(
+                "Unknown encoding: {label} in rule: {}",
+                self.pattern
+            ));
+        }
+        if let Some(color) = self.color.as_deref()
+            && color.parse::<Color>().is_err()
+        {
+            problems.push(format!("Unknown color: {color} in rule: {}", self.pattern));
+        }
+        problems
+    }
+}
+
+
+/// The settings of a single file, resolved from the rules matching it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileRules {
+    /// Show the file at all?
+    pub show: bool,
+
+    /// Syntax to highlight the file as, by name or file extension.
+    pub syntax: Option<String>,
+
+    /// Text encoding of the file, overriding the configured `encodings`.
+    pub encoding: Option<&'static Encoding>,
+
+    /// Bytes of tail shown when the file is newly watched.
+    pub tail_bytes: Option<u64>,
+
+    /// Lines of tail shown at startup and in one-shot mode.
+    pub tail_lines: Option<usize>,
+
+    /// Show only the lines containing one of these (all when empty).
+    pub show_lines: Vec<String>,
+
+    /// Hide the lines containing one of these.
+    pub hide_lines: Vec<String>,
+
+    /// Name shown in the header instead of the path.
+    pub label: Option<String>,
+
+    /// Color of the header.
+    pub color: Option<Color>,
+}
+
+
+impl Default for FileRules {
+    fn default() -> Self {
+        FileRules {
+            show: true,
+            syntax: None,
+            encoding: None,
+            tail_bytes: None,
+            tail_lines: None,
+            show_lines: Vec::new(),
+            hide_lines: Vec::new(),
+            label: None,
+            color: None,
+        }
+    }
+}
+
+
+impl FileRules {
+    /// Resolve the `rules` matching a file, in the order configured: the
+    /// settings of later rules override the ones of earlier rules.
+    pub fn resolve<'a>(rules: impl IntoIterator<Item = &'a Rule>) -> Self {
+        let mut resolved = FileRules::default();
+        for rule in rules {
+            if let Some(show) = rule.show {
+                resolved.show = show;
+            }
+            if let Some(syntax) = &rule.syntax {
+                resolved.syntax = Some(syntax.clone());
+            }
+            if let Some(encoding) = rule.encoding.as_deref().and_then(for_label) {
+                resolved.encoding = Some(encoding);
+            }
+            if let Some(tail_bytes) = rule.tail_bytes {
+                resolved.tail_bytes = Some(tail_bytes);
+            }
+            if let Some(tail_lines) = rule.tail_lines {
+                resolved.tail_lines = Some(tail_lines);
+            }
+            if let Some(show_lines) = &rule.show_lines {
+                resolved.show_lines = show_lines.clone();
+            }
+            if let Some(hide_lines) = &rule.hide_lines {
+                resolved.hide_lines = hide_lines.clone();
+            }
+            if let Some(label) = &rule.label {
+                resolved.label = Some(label.clone());
+            }
+            if let Some(color) = rule.color.as_deref().and_then(|color| color.parse().ok()) {
+                resolved.color = Some(color);
+            }
+        }
+        resolved
+    }
+
+
+    /// Whether only some lines of the file are shown.
+    pub fn filters_lines(&self) -> bool {
+        !self.show_lines.is_empty() || !self.hide_lines.is_empty()
+    }
+
+
+    /// The `lines` passing the [`Self::show_lines`] and [`Self::hide_lines`]
+    /// filters.
+    pub fn filter_lines(&self, mut lines: Vec<String>) -> Vec<String> {
+        if self.filters_lines() {
+            lines.retain(|line| {
+                (self.show_lines.is_empty()
+                    || self
+                        .show_lines
+                        .iter()
+                        .any(|text| line.contains(text.as_str())))
+                    && !self
+                        .hide_lines
+                        .iter()
+                        .any(|text| line.contains(text.as_str()))
+            });
+        }
+        lines
+    }
+
+
+    /// Header naming the file at `file_path`: its label or path, in its color.
+    pub fn header(&self, file_path: &str) -> ColoredString {
+        self.label
+            .as_deref()
+            .unwrap_or(file_path)
+            .color(self.color.unwrap_or(Color::Blue))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{FileRules, Rule};
+    use colored::Color;
+
+    fn rule(pattern: &str) -> Rule {
+        Rule {
+            pattern: pattern.to_string(),
+            ..Rule::default()
+        }
+    }
+
+    #[test]
+    fn later_rules_override_earlier_ones() {
+        let rules = [
+            Rule {
+                tail_lines: Some(5),
+                label: Some(String::from("app")),
+                color: Some(String::from("green")),
+                ..rule("*.log")
+            },
+            Rule {
+                tail_lines: Some(50),
+                show: Some(false),
+                ..rule("debug.log")
+            },
+        ];
+        let resolved = FileRules::resolve(&rules);
+        assert_eq!(resolved.tail_lines, Some(50));
+        assert!(!resolved.show);
+        assert_eq!(resolved.label.as_deref(), Some("app"));
+        assert_eq!(resolved.color, Some(Color::Green));
+        assert_eq!(FileRules::resolve(&[]), FileRules::default());
+    }
+
+    #[test]
+    fn lines_are_filtered_by_substring() {
+        let lines = ["INFO up", "ERROR down", "ERROR health check"].map(String::from);
+        let resolved = FileRules::resolve(&[Rule {
+            show_lines: Some(vec![String::from("ERROR")]),
+            hide_lines: Some(vec![String::from("health")]),
+            ..rule("*")
+        }]);
+        assert_eq!(resolved.filter_lines(lines.to_vec()), ["ERROR down"]);
+        assert_eq!(FileRules::default().filter_lines(lines.to_vec()).len(), 3);
+    }
+
+    #[test]
+    fn problems_name_the_rule() {
+        let problems = Rule {
+            encoding: Some(String::from("no-such-encoding")),
+            color: Some(String::from("plaid")),
+            ..rule("logs/[ab")
+        }
+        .problems();
+        assert_eq!(problems.len(), 2);
+        assert!(
+            problems
+                .iter()
+                .all(|problem| problem.ends_with(": logs/[ab"))
+        );
+    }
+}
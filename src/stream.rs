@@ -0,0 +1,401 @@
+//! Non-seekable sources watched next to regular files: the output of a
+//! command spawned by `lw`, stdin (`-`), named pipes and character devices.
+//!
+//! Every stream is read line by line on its own thread and handed over to the
+//! main loop through a channel, labeled with the name it is shown under (its
+//! "virtual file" name), so its lines go through the same output path as the
+//! content of watched files.
+
+use std::{
+    collections::HashSet,
+    fs::{File, metadata},
+    io::{self, BufRead, BufReader, Read},
+    os::unix::{fs::FileTypeExt, process::ExitStatusExt},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{Receiver, Sender, channel},
+    thread,
+};
+
+
+/// Path argument standing for stdin.
+pub const STDIN_PATH: &str = "-";
+
+
+/// Whether the path argument names a stream source rather than a file or
+/// directory to watch: stdin (`-`), a named pipe or a character device.
+pub fn is_stream_source(path: &str) -> bool {
+    path == STDIN_PATH
+        || metadata(path)
+            .map(|path_metadata| {
+                let file_type = path_metadata.file_type();
+                file_type.is_fifo() || file_type.is_char_device()
+            })
+            .unwrap_or(false)
+}
+
+
+/// What a reader thread reports to the main loop.
+#[derive(Debug)]
+enum StreamEvent {
+    /// A line read from the stream labeled `label`, starting at byte `position`
+    /// of the stream.
+    Line {
+        label: String,
+        position: u64,
+        line: String,
+    },
+
+    /// The stream labeled `label` reached its end (or failed to read).
+    Closed(String),
+}
+
+
+/// Consecutive lines read from one stream.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StreamChunk {
+    /// Name the stream is shown under.
+    pub label: String,
+
+    /// Byte offset of the first line within the stream.
+    pub position: u64,
+
+    /// The lines, without their line terminators.
+    pub lines: Vec<String>,
+}
+
+
+/// All stream sources of a running `lw`, plus the command it spawned, if any.
+#[derive(Debug)]
+pub struct Streams {
+    sender: Sender<StreamEvent>,
+    receiver: Receiver<StreamEvent>,
+    open: HashSet<String>,
+    command: Option<Child>,
+    command_labels: Vec<String>,
+}
+
+
+impl Default for Streams {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Streams {
+            sender,
+            receiver,
+            open: HashSet::new(),
+            command: None,
+            command_labels: Vec::new(),
+        }
+    }
+}
+
+
+impl Streams {
+    /// Start reading `reader` line by line on a background thread, showing its
+    /// lines under `label`.
+    pub fn add_reader(&mut self, label: &str, reader: impl Read + Send + 'static) {
+        let label = label.to_string();
+        let sender = self.sender.clone();
+        self.open.insert(label.clone());
+        thread::spawn(move || {
+            if let Err(error_cause) = read_lines(&label, reader, &mut 0, &sender) {
+                error!("Couldn't read stream: {label}. Caused by: {error_cause}");
+            }
+            let _ = sender.send(StreamEvent::Closed(label));
+        });
+    }
+
+
+    /// Start reading the stream source `path` (see [`is_stream_source`]): stdin
+    /// for `-`, otherwise the named pipe or character device at `path`. The
+    /// path is opened on the reader thread, as opening a pipe blocks until a
+    /// writer shows up. A pipe is reopened whenever its writer goes away, so
+    /// it keeps being followed across writers.
+    pub fn add_path(&mut self, path: &str) {
+        if path == STDIN_PATH {
+            self.add_reader("stdin", io::stdin());
+            return;
+        }
+        let label = path.to_string();
+        let sender = self.sender.clone();
+        self.open.insert(label.clone());
+        thread::spawn(move || {
+            let mut position = 0;
+            loop {
+                match File::open(&label) {
+                    Ok(file) => {
+                        let is_fifo = file
+                            .metadata()
+                            .map(|file_metadata| file_metadata.file_type().is_fifo())
+                            .unwrap_or(false);
+                        match read_lines(&label, file, &mut position, &sender) {
+                            Ok(true) if is_fifo => {}
+                            Ok(_) => break,
+
+                            // reopening wouldn't help, it would only spin
+                            Err(error_cause) => {
+                                error!(
+                                    "Couldn't read stream: {label}. Caused by: {error_cause}"
+                                );
+                                break;
+                            }
+                        }
+                    }
+
+                    Err(error_cause) => {
+                        error!("Couldn't open stream: {label}. Caused by: {error_cause}");
+                        break;
+                    }
+                }
+            }
+            let _ = sender.send(StreamEvent::Closed(label));
+        });
+    }
+
+
+    /// Spawn `command` (program followed by its arguments) and read its stdout
+    /// and stderr as two streams labeled `<program>:stdout` and
+    /// `<program>:stderr`.
+    pub fn spawn_command(&mut self, command: &[String]) -> io::Result<()> {
+        let (program, arguments) = command
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty command"))?;
+        let mut child = Command::new(program)
+            .args(arguments)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let name = program.rsplit('/').next().unwrap_or(program);
+        let stdout_label = format!("{name}:stdout");
+        let stderr_label = format!("{name}:stderr");
+        if let Some(stdout) = child.stdout.take() {
+            self.add_reader(&stdout_label, stdout);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            self.add_reader(&stderr_label, stderr);
+        }
+        self.command_labels = vec![stdout_label, stderr_label];
+        self.command = Some(child);
+        Ok(())
+    }
+
+
+    /// Collect all lines read so far, grouping consecutive lines of the same
+    /// stream into one chunk. Never blocks.
+    pub fn read_chunks(&mut self) -> Vec<StreamChunk> {
+        let mut chunks: Vec<StreamChunk> = Vec::new();
+        while let Ok(event) = self.receiver.try_recv() {
+            match event {
+                StreamEvent::Line {
+                    label,
+                    position,
+                    line,
+                } => {
+                    match chunks.last_mut() {
+                        Some(chunk) if chunk.label == label => chunk.lines.push(line),
+                        _ => {
+                            chunks.push(StreamChunk {
+                                label,
+                                position,
+                                lines: vec![line],
+                            })
+                        }
+                    }
+                }
+
+                StreamEvent::Closed(label) => {
+                    debug!("Stream closed: {label}");
+                    self.open.remove(&label);
+                }
+            }
+        }
+        chunks
+    }
+
+
+    /// Whether every stream source has reached its end.
+    pub fn all_closed(&self) -> bool {
+        self.open.is_empty()
+    }
+
+
+    /// Exit code of the spawned command, once it has exited and all of its
+    /// output has been read. A command killed by a signal yields `128 + signal`
+    /// like shells report it.
+    pub fn command_exit_code(&mut self) -> Option<i32> {
+        if self
+            .command_labels
+            .iter()
+            .any(|label| self.open.contains(label))
+        {
+            return None;
+        }
+        let status = self.command.as_mut()?.try_wait().ok()??;
+        Some(
+            status
+                .code()
+                .or_else(|| status.signal().map(|signal| 128 + signal))
+                .unwrap_or(1),
+        )
+    }
+}
+
+
+/// Reader thread body: send every line of `reader` labeled with `label`,
+/// advancing the stream `position`. Returns `true` at the end of its input,
+/// `false` once the main loop is gone and nobody listens anymore, and the
+/// error when reading failed.
+fn read_lines(
+    label: &str,
+    reader: impl Read,
+    position: &mut u64,
+    sender: &Sender<StreamEvent>,
+) -> io::Result<bool> {
+    for line in BufReader::new(reader).split(b'\n') {
+        let line = line?;
+        let line_position = *position;
+        *position += line.len() as u64 + 1;
+        let line = String::from_utf8_lossy(&line).into_owned();
+        let event = StreamEvent::Line {
+            label: label.to_string(),
+            position: line_position,
+            line,
+        };
+        if sender.send(event).is_err() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamChunk, Streams, is_stream_source, read_lines};
+    use std::{
+        io::{self, Read},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// Reader whose every read fails.
+    struct Failing;
+
+    impl Read for Failing {
+        fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("device gone"))
+        }
+    }
+
+    /// Longest a test waits for its streams to be done.
+    const DEADLINE: Duration = Duration::from_secs(10);
+
+    /// Read chunks until `done` says the streams are done, failing the test
+    /// when they aren't by the [`DEADLINE`].
+    fn read_until(
+        streams: &mut Streams,
+        mut done: impl FnMut(&mut Streams) -> bool,
+    ) -> Vec<StreamChunk> {
+        let deadline = Instant::now() + DEADLINE;
+        let mut chunks = Vec::new();
+        loop {
+            chunks.extend(streams.read_chunks());
+            if done(streams) {
+                return chunks;
+            }
+            assert!(Instant::now() < deadline, "streams not done in time");
+            thread::yield_now();
+        }
+    }
+
+    /// Read chunks until the spawned command is done, returning them with its
+    /// exit code.
+    fn run(command: &[&str]) -> (Vec<StreamChunk>, i32) {
+        let mut streams = Streams::default();
+        let command: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+        streams.spawn_command(&command).unwrap();
+        let mut exit_code = None;
+        let chunks = read_until(&mut streams, |streams| {
+            exit_code = streams.command_exit_code();
+            exit_code.is_some()
+        });
+        (chunks, exit_code.unwrap())
+    }
+
+    #[test]
+    fn reader_lines_are_grouped_with_positions() {
+        let mut streams = Streams::default();
+        // read on this thread, so every line is queued before they're collected
+        assert!(read_lines("input", &b"one\ntwo\n"[..], &mut 0, &streams.sender).unwrap());
+        assert_eq!(
+            streams.read_chunks(),
+            vec![StreamChunk {
+                label: "input".to_string(),
+                position: 0,
+                lines: vec!["one".to_string(), "two".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn reader_closes_at_end_of_input() {
+        let mut streams = Streams::default();
+        streams.add_reader("input", &b"last\n"[..]);
+        assert!(
+            !streams.all_closed(),
+            "closing is only noticed by read_chunks"
+        );
+        let chunks = read_until(&mut streams, |streams| streams.all_closed());
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].lines, ["last"]);
+    }
+
+    #[test]
+    fn read_error_is_reported_and_closes_the_stream() {
+        let mut streams = Streams::default();
+        assert!(read_lines("failing", Failing, &mut 0, &streams.sender).is_err());
+        streams.add_reader("failing", Failing);
+        let chunks = read_until(&mut streams, |streams| streams.all_closed());
+        assert!(chunks.is_empty());
+    }
+
+    #[test]
+    fn stdin_pipes_and_devices_are_stream_sources() {
+        assert!(is_stream_source("-"));
+        assert!(is_stream_source("/dev/null"));
+        assert!(!is_stream_source("/"));
+        assert!(!is_stream_source("/nonexistent/lw-test-path"));
+        let path = std::env::temp_dir().join(format!("lw_stream_test_{}", std::process::id()));
+        std::fs::write(&path, "regular\n").unwrap();
+        assert!(!is_stream_source(&path.to_string_lossy()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn command_output_is_labeled_and_exit_code_kept() {
+        let (chunks, exit_code) = run(&["sh", "-c", "echo out; echo err >&2; exit 3"]);
+        assert_eq!(exit_code, 3);
+        let stdout: Vec<_> = chunks
+            .iter()
+            .filter(|chunk| chunk.label == "sh:stdout")
+            .flat_map(|chunk| chunk.lines.clone())
+            .collect();
+        let stderr: Vec<_> = chunks
+            .iter()
+            .filter(|chunk| chunk.label == "sh:stderr")
+            .flat_map(|chunk| chunk.lines.clone())
+            .collect();
+        assert_eq!(stdout, vec!["out"]);
+        assert_eq!(stderr, vec!["err"]);
+    }
+
+    #[test]
+    fn missing_program_fails_to_spawn() {
+        let mut streams = Streams::default();
+        assert!(
+            streams
+                .spawn_command(&["/nonexistent/lw-test-program".to_string()])
+                .is_err()
+        );
+        assert!(streams.spawn_command(&[]).is_err());
+    }
+}
@@ -1,11 +1,65 @@
-//! Shared type aliases used across the crate.
+//! Shared types used across the crate.
 
-use std::collections::HashMap;
+use crate::{compression::Compression, rules::FileRules};
+use encoding_rs::Encoding;
+use std::{
+    cell::{Cell, OnceCell},
+    collections::HashMap,
+    sync::Arc,
+};
 
 /// Per-file watch state: the file's inode number and the last read byte
 /// position. The inode lets us detect when a path was replaced by a brand new
 /// file (atomic rename, log rotation) so we can re-read it from the start.
-pub type FileState = (u64, u64);
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileState {
+    /// Inode number of the file.
+    pub inode: u64,
+
+    /// Last read byte position.
+    pub position: u64,
+
+    /// Compression format of the file, sniffed when it was first read from its
+    /// start.
+    pub compression: Option<Compression>,
+
+    /// Compressed content was decoded to its end and its tail shown, so the
+    /// file (done being written) isn't shown again until it's replaced.
+    pub decompressed: Cell<bool>,
+
+    /// Text encoding of the file and the length of its byte order mark, sniffed
+    /// when it was first read from its start; `None` for UTF-8.
+    pub encoding: Option<(&'static Encoding, u64)>,
+
+    /// Content was sniffed as binary, so its changes are summarized (or
+    /// suppressed) instead of read as lines.
+    pub binary: bool,
+
+    /// Settings of the file from the configured rules, resolved when it was
+    /// first registered.
+    pub rules: Arc<FileRules>,
+
+    /// Index of the syntax its content is highlighted as (see
+    /// [`crate::highlight::Highlighter::find`]), detected on its first read
+    /// after it was read from its start; `None` inside when none matched.
+    pub syntax: OnceCell<Option<usize>>,
+}
+
+impl FileState {
+    /// State of a text file read up to `position`.
+    pub fn new(inode: u64, position: u64) -> Self {
+        FileState {
+            inode,
+            position,
+            compression: None,
+            decompressed: Cell::new(false),
+            encoding: None,
+            binary: false,
+            rules: Arc::default(),
+            syntax: OnceCell::new(),
+        }
+    }
+}
 
 /// Maps a watched file path to its [`FileState`].
 pub type FileAndPosition = HashMap<String, FileState>;
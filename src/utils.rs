@@ -1,17 +1,36 @@
 //! Utility functions: directory walking, kqueue watch management, event
 //! handling and the pure decision helpers that drive them.
 
+use crate::binary::{BinaryFiles, format_size, is_binary_file, read_hexdump};
+use crate::cli::TailOrder;
+use crate::compression::Compression;
 use crate::config::Config;
+use crate::encoding::{self, read_decoded_last_lines, read_decoded_lines};
+use crate::glob::{Glob, matches_any};
+use crate::highlight::highlighter;
+use crate::ignore_files;
+use crate::rules::FileRules;
+use crate::stream::StreamChunk;
 use crate::types::{FileAndPosition, FileState};
+use crate::watcher::KqueueWatcher;
 use colored::Colorize;
-use kqueue2::*;
+use encoding_rs::Encoding;
 use std::{
+    cell::{Cell, OnceCell},
     fs::{File, OpenOptions, metadata},
-    io::{BufReader, IsTerminal, SeekFrom, Write, prelude::*},
+    io::{self, BufReader, IsTerminal, SeekFrom, Write, prelude::*},
     os::unix::fs::MetadataExt,
     path::Path,
+    sync::Arc,
 };
-use walkdir::WalkDir;
+use walkdir::{DirEntry, DirEntryExt, WalkDir};
+
+
+/// Block size used when scanning a file backwards for its last lines.
+const TAIL_SCAN_BLOCK: usize = 8192;
+
+/// Most bytes read of the first line of a file for syntax detection.
+const FIRST_LINE_BYTES: u64 = 1024;
 
 
 /// Write-once-and-atomic to a file
@@ -33,96 +52,181 @@ pub fn write_append(file_path: &str, contents: &str) {
 }
 
 
-/// Minimal shell-style glob match supporting `*` (any run of characters) and
-/// `?` (a single character). Matches the whole `name` against `pattern`.
-///
-/// Pure recursive descent over the two char slices:
-/// - `*` matches zero characters (advance the pattern), or one character then
-///   retries `*` (advance the name);
-/// - `?` matches exactly one character;
-/// - any other character matches itself.
-fn glob_match(name: &[char], pattern: &[char]) -> bool {
-    match pattern.split_first() {
-        None => name.is_empty(),
-        Some((&'*', rest)) => {
-            glob_match(name, rest)
-                || matches!(name.split_first(), Some((_, tail)) if glob_match(tail, pattern))
-        }
-        Some((&'?', rest)) => {
-            matches!(name.split_first(), Some((_, tail)) if glob_match(tail, rest))
-        }
-        Some((&expected, rest)) => {
-            matches!(name.split_first(), Some((&first, tail)) if first == expected && glob_match(tail, rest))
-        }
-    }
+/// Convenience wrapper over [`Glob`] for whole `&str` inputs (test-only).
+#[cfg(test)]
+fn matches_glob(name: &str, pattern: &str) -> bool {
+    Glob::new(pattern).matches(Path::new(name), false)
 }
 
 
-/// Convenience wrapper over [`glob_match`] for whole `&str` inputs (test-only).
-#[cfg(test)]
-fn matches_glob(name: &str, pattern: &str) -> bool {
-    let name: Vec<char> = name.chars().collect();
-    let pattern: Vec<char> = pattern.chars().collect();
-    glob_match(&name, &pattern)
+/// Whether `path` (relative to its watched root) should be ignored, i.e. it or
+/// one of its parent directories matches any of the given precompiled `globs`.
+/// Used to skip transient temp/swap/backup files and excluded directories.
+fn is_ignored(path: &Path, is_dir: bool, globs: &[Glob]) -> bool {
+    matches_any(globs, path, is_dir)
 }
 
 
-/// Whether the file at `path` should be ignored, i.e. its file name matches any
-/// of the given precompiled `globs`. Used to skip transient temp/swap/backup
-/// files.
-fn is_ignored(path: &Path, globs: &[Vec<char>]) -> bool {
-    match path.file_name().and_then(|name| name.to_str()) {
-        // Collect the file name's chars once, then test each glob against it.
-        Some(name) => {
-            let name: Vec<char> = name.chars().collect();
-            globs.iter().any(|glob| glob_match(&name, glob))
-        }
-        None => false,
-    }
+/// Whether `path` is ignored by the `.gitignore`, `.ignore` and `.lwignore`
+/// files of its watched root, when those are respected.
+fn is_ignored_by_ignore_files(path: &Path, is_dir: bool, config: &Config) -> bool {
+    config.ignore_files.unwrap_or_default()
+        && config
+            .root_of(path)
+            .is_some_and(|root| config.ignore_file_rules().is_ignored(root, path, is_dir))
+}
+
+
+/// Whether the file at `path` (relative to its watched root) is one to watch:
+/// any file when there are no include `globs`, otherwise only matching ones.
+/// A root given as argument (empty relative path) is always watched.
+fn is_included(path: &Path, globs: &[Glob]) -> bool {
+    globs.is_empty() || path.as_os_str().is_empty() || matches_any(globs, path, false)
+}
+
+
+/// Whether the file at `path` is one to watch: selected by the configured
+/// include patterns and, below a root given as glob argument, by its pattern.
+fn is_watched_file(path: &Path, config: &Config) -> bool {
+    let relative_path = config.relative_path(path);
+    is_included(relative_path, config.include_globs())
+        && config
+            .root_patterns(path)
+            .is_none_or(|patterns| is_included(relative_path, patterns))
+}
+
+
+/// Whether `path` is one to watch: not ignored by the ignore patterns or
+/// ignore files and, if it is a file, selected by the include patterns.
+fn is_watched_path(path: &Path, is_dir: bool, config: &Config) -> bool {
+    !is_ignored(config.relative_path(path), is_dir, config.ignore_globs())
+        && (is_dir || is_watched_file(path, config))
+        && !is_ignored_by_ignore_files(path, is_dir, config)
 }
 
 
-/// Resursively filter out all unreadable/unaccessible/inproper and handle proper files
+/// Resursively filter out all unreadable/unaccessible/inproper and handle proper files.
+/// `at_startup` is set for the initial walk over the paths given as arguments,
+/// and cleared for re-walks of directories that changed while running.
 pub fn walkdir_recursive(
-    kqueue_watcher: &mut Watcher,
+    kqueue_watcher: &mut KqueueWatcher,
     watched_file_states: &mut FileAndPosition,
     last_file: &mut String,
     file_path: &Path,
     config: &Config,
+    at_startup: bool,
 ) {
-    let ignore_globs = config.ignore_globs();
-    WalkDir::new(file_path)
+    // directories are yielded before their contents (not `contents_first`), as
+    // `filter_entry` can only prune the ones it sees before descending into them
+    let entries: Vec<DirEntry> = WalkDir::new(file_path)
         .same_file_system(false)
-        .contents_first(true)
         .follow_links(config.follow_links.unwrap_or_default())
         .max_open(config.max_open_files.unwrap_or_default())
         .max_depth(config.max_dir_depth.unwrap_or_default())
         .into_iter()
+        // on re-walks, subdirectories that are already watched report their own changes
+        // (unless they were replaced by another directory at the same path),
+        // and excluded directories are neither watched nor descended into;
+        // include patterns only select files, directories are always walked
+        .filter_entry(|element| {
+            let is_dir = element.file_type().is_dir();
+            element.depth() == 0
+                || ((at_startup
+                    || !is_dir
+                    || !kqueue_watcher.is_registered_as(element.path(), element.ino()))
+                    && is_watched_path(element.path(), is_dir, config))
+        })
         .filter_map(|element| element.ok())
-        .filter(|element| !is_ignored(element.path(), ignore_globs))
-        .for_each(|element| {
-            watch_file(
-                kqueue_watcher,
-                watched_file_states,
-                last_file,
-                element.path(),
-            )
-        });
+        // pipes, sockets and devices can't be followed like files (opening a
+        // pipe blocks); stream sources are only taken as explicit arguments.
+        // Symlinks not followed are still watched when they point to a file.
+        .filter(|element| {
+            let file_type = element.file_type();
+            file_type.is_file()
+                || file_type.is_dir()
+                || (file_type.is_symlink()
+                    && metadata(element.path()).is_ok_and(|target| target.is_file()))
+        })
+        .collect();
+    entries.iter().for_each(|element| {
+        watch_file(
+            kqueue_watcher,
+            watched_file_states,
+            last_file,
+            element.path(),
+            config,
+            at_startup,
+        )
+    });
+}
+
+
+/// Stop watching the files and directories below `directory` that are
+/// ignored by the ignore files now.
+fn unwatch_ignored(
+    kqueue_watcher: &mut KqueueWatcher,
+    watched_file_states: &mut FileAndPosition,
+    directory: &Path,
+    config: &Config,
+) {
+    for watched_path in kqueue_watcher.paths() {
+        let path = Path::new(&watched_path);
+        let is_file = watched_file_states.contains_key(&watched_path);
+        // other files (like the configuration file) aren't watched as content
+        if path == directory || !path.starts_with(directory) || !is_file && !path.is_dir() {
+            continue;
+        }
+        if is_ignored_by_ignore_files(path, !is_file, config) {
+            trace!("{}: {}", "-Ignored".magenta(), watched_path.cyan());
+            kqueue_watcher.unregister(path);
+            watched_file_states.remove(&watched_path);
+        }
+    }
 }
 
 
 /// Process file with event
 pub fn process_file_event(
     abs_file_name: &str,
-    kqueue_watcher: &mut Watcher,
+    kqueue_watcher: &mut KqueueWatcher,
     watched_file_states: &mut FileAndPosition,
     last_file: &mut String,
     config: &Config,
 ) {
     let file_path = Path::new(&abs_file_name);
+    let is_dir = file_path.is_dir();
+    // changed ignore files (or a changed directory, which may have gained or
+    // lost some) are read again. When their rules changed, the paths below the
+    // directory ignored now are unwatched, and it is re-walked to pick up the
+    // files that aren't ignored anymore.
+    if config.ignore_files.unwrap_or_default() {
+        let changed_directory = if is_dir {
+            Some(file_path)
+        } else if ignore_files::is_ignore_file(file_path) {
+            file_path.parent()
+        } else {
+            None
+        };
+        if let Some(directory) = changed_directory
+            && config.ignore_file_rules().reload(directory)
+        {
+            unwatch_ignored(kqueue_watcher, watched_file_states, directory, config);
+            if !is_dir {
+                walkdir_recursive(
+                    kqueue_watcher,
+                    watched_file_states,
+                    last_file,
+                    directory,
+                    config,
+                    false,
+                );
+            }
+        }
+    }
     // Skip transient temp/swap/backup files (e.g. rustfmt's `foo.rs.tmp.PID`);
     // the real file's own rename event shows the diff under its proper name.
-    if is_ignored(file_path, config.ignore_globs()) {
+    // Files not matching the include patterns are skipped too.
+    if !is_watched_path(file_path, is_dir, config) {
         trace!("{}: {}", "-Ignored".magenta(), abs_file_name.cyan());
         return;
     }
@@ -136,6 +240,7 @@ pub fn process_file_event(
                     last_file,
                     file_path,
                     config,
+                    false,
                 );
             } else {
                 trace!("{}: {}", "+FileWatchHandle".magenta(), abs_file_name.cyan());
@@ -152,18 +257,7 @@ pub fn process_file_event(
 
         Err(error_cause) => {
             // handle situation when logs are wiped out and unavailable to read anymore
-            kqueue_watcher
-                .remove_filename(file_path, EventFilter::EVFILT_VNODE)
-                .inspect(|_| {
-                    trace!("{}: {}", "-Watch".magenta(), abs_file_name.cyan());
-                })
-                .unwrap_or_else(|error| {
-                    error!(
-                        "Could not remove watch on file: {:?}. Error cause: {}",
-                        abs_file_name.cyan(),
-                        error.to_string().red()
-                    )
-                });
+            kqueue_watcher.unregister(file_path);
             // try to build list if path exists
             if file_path.exists() {
                 if file_path.is_dir() {
@@ -174,9 +268,17 @@ pub fn process_file_event(
                         last_file,
                         file_path,
                         config,
+                        false,
                     );
                 } else if file_path.is_file() {
-                    watch_file(kqueue_watcher, watched_file_states, last_file, file_path);
+                    watch_file(
+                        kqueue_watcher,
+                        watched_file_states,
+                        last_file,
+                        file_path,
+                        config,
+                        false,
+                    );
                 }
             } else {
                 debug!(
@@ -201,9 +303,57 @@ pub fn process_file_event(
 }
 
 
+/// Re-evaluate what is watched after the configuration was reloaded: paths
+/// below the roots that aren't to be watched anymore are dropped, and the
+/// roots walked again for the ones that now are. Files still watched keep
+/// their positions, with their rules resolved and their syntax detected
+/// again; the ones hidden by their rules now are unwatched.
+pub fn rewatch_with_config(
+    kqueue_watcher: &mut KqueueWatcher,
+    watched_file_states: &mut FileAndPosition,
+    last_file: &mut String,
+    config: &Config,
+) {
+    let is_dropped = |path: &Path, is_dir: bool| {
+        config.root_of(path).is_some() && !is_watched_path(path, is_dir, config)
+    };
+    for watched_path in kqueue_watcher.paths() {
+        let path = Path::new(&watched_path);
+        if is_dropped(path, path.is_dir()) {
+            trace!("{}: {}", "-Unwatch".magenta(), watched_path.cyan());
+            kqueue_watcher.unregister(path);
+        }
+    }
+    watched_file_states.retain(|file_path, state| {
+        let path = Path::new(file_path);
+        if is_dropped(path, false) {
+            return false;
+        }
+        state.rules = config.rules_for(path);
+        if !state.rules.show {
+            trace!("{}: {}", "-Hidden".magenta(), file_path.cyan());
+            kqueue_watcher.unregister(path);
+            return false;
+        }
+        state.syntax = OnceCell::new();
+        true
+    });
+    for root in config.root_paths() {
+        walkdir_recursive(
+            kqueue_watcher,
+            watched_file_states,
+            last_file,
+            Path::new(&root),
+            config,
+            true,
+        );
+    }
+}
+
+
 /// Decide which byte offset to start reading a file from, given what we knew
 /// about it before this event (`previous`), its current `inode` and `file_size`,
-/// and the configured `tail_bytes`.
+/// and the `tail_bytes` configured for it.
 ///
 /// - Known file, same inode, cursor within bounds -> continue (show only the
 ///   newly appended data).
@@ -214,17 +364,17 @@ pub fn process_file_event(
 /// - Never seen before -> skip to the tail so we don't dump the whole
 ///   pre-existing content (mirrors `tail -F` behaviour).
 fn decide_read_position(
-    previous: Option<FileState>,
+    previous: Option<&FileState>,
     inode: u64,
     file_size: u64,
     tail_bytes: u64,
 ) -> u64 {
     match previous {
-        Some((last_inode, last_position)) if last_inode == inode => {
-            if last_position > file_size {
+        Some(state) if state.inode == inode => {
+            if state.position > file_size {
                 0
             } else {
-                last_position
+                state.position
             }
         }
         Some(_) => 0,
@@ -242,43 +392,273 @@ fn calculate_position_and_handle(
     last_file: &mut String,
     config: &Config,
 ) {
+    let path = Path::new(abs_file_name);
+    let previous = watched_file_states.remove(abs_file_name);
+    let rules = previous
+        .as_ref()
+        .map_or_else(|| config.rules_for(path), |state| Arc::clone(&state.rules));
     let position = decide_read_position(
-        watched_file_states.get(abs_file_name).copied(),
+        previous.as_ref(),
         inode,
         file_size,
-        config.tail_bytes.unwrap_or_default(),
+        rules.tail_bytes.or(config.tail_bytes).unwrap_or_default(),
     );
-
-    handle_file_event(position, file_size, abs_file_name, last_file);
-
     // Record the current inode and end offset so the next event shows only
     // newly added data (or a full re-read if the file is replaced/truncated).
-    watched_file_states.insert(abs_file_name.to_string(), (inode, file_size));
+    // Its content is sniffed again whenever it's read from its start: it was
+    // replaced, truncated, or empty when first seen.
+    let state = match previous {
+        Some(state) if position > 0 => {
+            FileState {
+                inode,
+                position: file_size,
+                ..state
+            }
+        }
+        _ => sniffed_state(path, inode, file_size, rules, config),
+    };
+    handle_file_event(
+        position,
+        file_size,
+        abs_file_name,
+        &state,
+        last_file,
+        config,
+    );
+    watched_file_states.insert(abs_file_name.to_string(), state);
 }
 
 
-/// Kqueue wrapper for watch()
-pub fn watch_the_watcher(kqueue_watcher: &mut Watcher) {
-    trace!("{}: watch()", "+Trigger".magenta());
-    kqueue_watcher.watch().unwrap_or_default();
+/// Print the last `tail_lines` lines of watched files: every file in one-shot
+/// (`--once`) mode, or the initial tails shown at startup before following.
+/// With a non-zero `max_files` only that many most recently modified files are
+/// shown, so pointing `lw` at a large tree doesn't flood the terminal.
+pub fn print_tails(
+    watched_file_states: &FileAndPosition,
+    last_file: &mut String,
+    config: &Config,
+    order: TailOrder,
+    max_files: usize,
+) {
+    let mut files = sorted_files(watched_file_states, TailOrder::Mtime);
+    if max_files > 0 {
+        files = files.split_off(files.len().saturating_sub(max_files));
+    }
+    if order == TailOrder::Path {
+        files.sort();
+    }
+    for (file_path, file_size) in files {
+        let Some(state) = watched_file_states.get(&file_path) else {
+            continue;
+        };
+        let rules = &state.rules;
+        if state.binary || !rules.show {
+            handle_file_event(0, file_size, &file_path, state, last_file, config);
+            continue;
+        }
+        let tail_lines = rules.tail_lines.or(config.tail_lines).unwrap_or_default();
+        let (position, content) = match state.encoding {
+            Some((encoding, bom_length)) => {
+                match read_decoded_last_lines(
+                    Path::new(&file_path),
+                    file_size,
+                    tail_lines,
+                    encoding,
+                    bom_length,
+                ) {
+                    Ok(tail) => tail,
+                    Err(error_cause) => {
+                        error!(
+                            "Couldn't read tail of file: {}. Caused by: {}",
+                            file_path.cyan(),
+                            error_cause.to_string().red()
+                        );
+                        continue;
+                    }
+                }
+            }
+            None => read_last_lines(&file_path, file_size, tail_lines, state.compression),
+        };
+        // an empty file (or tail) has nothing to show, not even its header
+        if content.is_empty() {
+            trace!("{}: {}", "-Empty".magenta(), file_path.cyan());
+            continue;
+        }
+        if state.compression.is_some() && !content.is_empty() {
+            state.decompressed.set(true);
+        }
+        print_text_content(
+            position,
+            &file_path,
+            Some(content),
+            rules,
+            || file_syntax(&file_path, state, config),
+            last_file,
+        );
+    }
 }
 
 
-/// kqueue flags, from: /usr/include/sys/event.h
-/// NOTE_DELETE     0x00000001              /* vnode was removed */
-/// NOTE_WRITE      0x00000002              /* data contents changed */
-/// NOTE_EXTEND     0x00000004              /* size increased */
-/// NOTE_ATTRIB     0x00000008              /* attributes changed */
-/// NOTE_LINK       0x00000010              /* link count changed */
-/// NOTE_RENAME     0x00000020              /* vnode was renamed */
-/// NOTE_REVOKE     0x00000040              /* vnode access was revoked */
-///
-/// Add watch on specified file path
+/// Watched files with their sizes, ordered by path or by modification time
+/// (most recently modified last, so the freshest tail ends up at the bottom).
+fn sorted_files(
+    watched_file_states: &FileAndPosition,
+    order: TailOrder,
+) -> Vec<(String, u64)> {
+    let mut files: Vec<(String, u64)> = watched_file_states
+        .iter()
+        .map(|(file_path, state)| (file_path.clone(), state.position))
+        .collect();
+    match order {
+        TailOrder::Path => files.sort(),
+        TailOrder::Mtime => {
+            files.sort_by_cached_key(|(file_path, _)| {
+                (
+                    metadata(file_path).and_then(|meta| meta.modified()).ok(),
+                    file_path.clone(),
+                )
+            })
+        }
+    }
+    files
+}
+
+
+/// Process every watched file that changed once more, so whatever was
+/// appended since the last event is printed before `lw` exits.
+pub fn drain_watched_files(
+    kqueue_watcher: &mut KqueueWatcher,
+    watched_file_states: &mut FileAndPosition,
+    last_file: &mut String,
+    config: &Config,
+) {
+    // Only files that changed since their last event; the rest would just
+    // print empty headers.
+    let mut file_paths: Vec<String> = watched_file_states
+        .iter()
+        .filter(|&(file_path, state)| {
+            metadata(file_path)
+                .map(|file_metadata| {
+                    file_metadata.ino() != state.inode || file_metadata.len() != state.position
+                })
+                .unwrap_or(true)
+        })
+        .map(|(file_path, _)| file_path.clone())
+        .collect();
+    file_paths.sort();
+    for file_path in file_paths {
+        process_file_event(
+            &file_path,
+            kqueue_watcher,
+            watched_file_states,
+            last_file,
+            config,
+        );
+    }
+}
+
+
+/// Poll the files evicted from the watch budget: the ones that changed since
+/// they were last read are processed like on a watcher event, and promoted
+/// back to a watch as their activity makes them the most recent.
+pub fn poll_evicted_files(
+    kqueue_watcher: &mut KqueueWatcher,
+    watched_file_states: &mut FileAndPosition,
+    last_file: &mut String,
+    config: &Config,
+) {
+    for file_path in kqueue_watcher.evicted() {
+        let changed = match (metadata(&file_path), watched_file_states.get(&file_path)) {
+            (Ok(file_metadata), Some(state)) => {
+                file_metadata.ino() != state.inode || file_metadata.len() != state.position
+            }
+            _ => true,
+        };
+        if changed {
+            trace!("{}: {}", "+Promote".magenta(), file_path.cyan());
+            process_file_event(
+                &file_path,
+                kqueue_watcher,
+                watched_file_states,
+                last_file,
+                config,
+            );
+            if let Ok(file_metadata) = metadata(&file_path)
+                && file_metadata.is_file()
+            {
+                kqueue_watcher
+                    .promote(Path::new(&file_path), file_metadata.ino())
+                    .unwrap_or_else(|error_cause| {
+                        error!(
+                            "Could not watch file: {}. Caused by: {}",
+                            file_path.cyan(),
+                            error_cause.to_string().red()
+                        );
+                        false
+                    });
+            }
+        }
+    }
+}
+
+
+/// Add an exit watch on process `pid`. Returns `false` when the process can't
+/// be watched: it has already exited (`ESRCH`), or the watch failed.
+pub fn watch_pid(kqueue_watcher: &mut KqueueWatcher, pid: i32) -> bool {
+    kqueue_watcher
+        .add_pid(pid)
+        .inspect(|_| {
+            trace!("{}: {}", "+WatchPid".magenta(), format!("{pid}").cyan());
+        })
+        .map_err(|error_cause| {
+            if error_cause.raw_os_error() == Some(libc::ESRCH) {
+                debug!("Process already exited: {}", format!("{pid}").cyan())
+            } else {
+                error!(
+                    "Could not watch process: {}. Caused by: {}",
+                    format!("{pid}").cyan(),
+                    error_cause.to_string().red()
+                )
+            }
+        })
+        .is_ok()
+}
+
+
+/// Whether process `pid` is still running (probed with an exit watch on a
+/// kqueue of its own, which a process that's gone refuses with `ESRCH`). Used
+/// as a fallback next to the kqueue `EVFILT_PROC` exit events.
+pub fn process_exists(pid: i32) -> bool {
+    KqueueWatcher::new(0)
+        .and_then(|mut probe| probe.add_pid(pid))
+        .map_or_else(
+            |error_cause| error_cause.raw_os_error() != Some(libc::ESRCH),
+            |_| true,
+        )
+}
+
+
+/// Kqueue wrapper for watch(). Cheap when no watches were added or removed
+/// since the previous call.
+pub fn watch_the_watcher(kqueue_watcher: &mut KqueueWatcher) {
+    kqueue_watcher.watch().unwrap_or_else(|error_cause| {
+        error!(
+            "Could not submit the watch list. Caused by: {}",
+            error_cause.to_string().red()
+        )
+    });
+}
+
+
+/// Add watch on specified file path, unless it is already watched with the
+/// same inode
 fn watch_file(
-    kqueue_watcher: &mut Watcher,
+    kqueue_watcher: &mut KqueueWatcher,
     watched_file_states: &mut FileAndPosition,
     last_file: &mut String,
     file: &Path,
+    config: &Config,
+    at_startup: bool,
 ) {
     // Seed/refresh per-file state:
     // - brand new path -> only seed the current size, so startup (and directory
@@ -287,50 +667,149 @@ fn watch_file(
     //   rename / rotation (e.g. `rustfmt` renaming its temp file over the
     //   original), so show its new content from the start under the real name.
     //   This makes replacement detection work even when the file's own vnode
-    //   event is lost to the concurrent directory re-walk.
-    if let Ok(file_metadata) = metadata(file)
-        && file_metadata.is_file()
-    {
-        let inode = file_metadata.ino();
+    //   event is lost to the concurrent directory re-walk;
+    // - compressed file appearing after startup -> a freshly rotated log that
+    //   will never be appended to, so show its decompressed tail right away.
+    let Ok(file_metadata) = metadata(file) else {
+        return;
+    };
+    let inode = file_metadata.ino();
+    if file_metadata.is_file() {
         let size = file_metadata.len();
         let key = file.to_string_lossy().to_string();
-        match watched_file_states.get(&key).copied() {
-            Some((stored_inode, _)) if stored_inode != inode => {
-                handle_file_event(0, size, &key, last_file);
-                watched_file_states.insert(key, (inode, size));
+        match watched_file_states.get(&key) {
+            Some(state) if state.inode != inode => {
+                let rules = Arc::clone(&state.rules);
+                let state = sniffed_state(file, inode, size, rules, config);
+                handle_file_event(0, size, &key, &state, last_file, config);
+                watched_file_states.insert(key, state);
             }
             Some(_) => {}
             None => {
-                watched_file_states.insert(key, (inode, size));
+                // rules are resolved and the content sniffed once, here, and
+                // cached with the state. Hidden files aren't watched at all:
+                // neither sniffed nor taking a watch from the budget
+                let rules = config.rules_for(file);
+                if !rules.show {
+                    trace!("{}: {}", "-Hidden".magenta(), key.cyan());
+                    return;
+                }
+                let state = sniffed_state(file, inode, size, rules, config);
+                if !at_startup && state.compression.is_some() {
+                    handle_file_event(0, size, &key, &state, last_file, config);
+                }
+                watched_file_states.insert(key, state);
             }
         }
     }
     kqueue_watcher
-        .remove_filename(file, EventFilter::EVFILT_VNODE)
-        .inspect(|_| {
-            trace!("{}: {}", "-Watch".magenta(), format!("{file:?}").cyan());
-        })
-        .unwrap_or_default();
-    kqueue_watcher
-        .add_filename(
-            file,
-            EventFilter::EVFILT_VNODE,
-            NOTE_WRITE | NOTE_LINK | NOTE_RENAME | NOTE_DELETE | NOTE_EXTEND, // | NOTE_ATTRIB
-                                                                              // | NOTE_REVOKE,
-        )
-        .inspect(|_| {
-            trace!("{}: {}", "+Watch".magenta(), format!("{file:?}").cyan());
-        })
+        .register(file, inode, file_metadata.is_file())
         .unwrap_or_else(|error_cause| {
             error!(
                 "Could not watch file: {}. Caused by: {}",
                 format!("{file:?}").cyan(),
                 error_cause.to_string().red()
-            )
+            );
+            false
+        });
+}
+
+
+/// Watch the configuration file at `config_path` for changes, following it
+/// to its new inode after an editor replaced it. It is registered like a
+/// directory, so it is never evicted from the watch budget.
+pub fn watch_config_file(kqueue_watcher: &mut KqueueWatcher, config_path: &str) {
+    let Ok(file_metadata) = metadata(config_path) else {
+        return;
+    };
+    kqueue_watcher
+        .register(Path::new(config_path), file_metadata.ino(), false)
+        .unwrap_or_else(|error_cause| {
+            error!(
+                "Could not watch configuration file: {}. Caused by: {}",
+                config_path.cyan(),
+                error_cause.to_string().red()
+            );
+            false
         });
 }
 
 
+/// State of the file at `path`, read from its start up to `position`: its
+/// compression, encoding and whether it's binary are sniffed, and its syntax
+/// detected, here once and cached until it's read from its start again.
+/// Compressed files are decompressed, and files in a known text encoding
+/// decoded, rather than sniffed as binary.
+fn sniffed_state(
+    path: &Path,
+    inode: u64,
+    position: u64,
+    rules: Arc<FileRules>,
+    config: &Config,
+) -> FileState {
+    let compression = Compression::detect(path);
+    let encoding = compression
+        .is_none()
+        .then(|| file_encoding(path, config, &rules))
+        .flatten();
+    FileState {
+        inode,
+        position,
+        compression,
+        decompressed: Cell::new(false),
+        encoding,
+        binary: compression.is_none() && encoding.is_none() && is_binary_file(path),
+        syntax: OnceCell::new(),
+        rules,
+    }
+}
+
+
+/// Syntax of the watched file at `file_path`, detected on its first read (see
+/// [`detect_syntax`]) and cached with its `state`.
+fn file_syntax(file_path: &str, state: &FileState, config: &Config) -> Option<usize> {
+    *state
+        .syntax
+        .get_or_init(|| detect_syntax(Path::new(file_path), &state.rules, config))
+}
+
+
+/// Syntax of the file at `path` to highlight its content as (see
+/// [`crate::highlight::Highlighter::detect`]): the one named by its rules or
+/// the configured `syntaxes`, or else detected by its name and first line.
+/// Only terminal output is highlighted, so nothing is detected for any other.
+fn detect_syntax(path: &Path, rules: &FileRules, config: &Config) -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let explicit = rules.syntax.as_deref().or_else(|| config.syntax_for(path));
+    highlighter().detect(path, explicit, || read_first_line(path))
+}
+
+
+/// First line of the file at `path`, read for syntax detection (at most
+/// [`FIRST_LINE_BYTES`] of it). `None` when it's empty or not UTF-8.
+fn read_first_line(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let mut line = String::new();
+    BufReader::new(file.take(FIRST_LINE_BYTES))
+        .read_line(&mut line)
+        .ok()?;
+    (!line.is_empty()).then_some(line)
+}
+
+
+/// Encoding of the file at `path` (by byte order mark, its rules or the
+/// configuration) and the length of its byte order mark; `None` for UTF-8.
+fn file_encoding(
+    path: &Path,
+    config: &Config,
+    rules: &FileRules,
+) -> Option<(&'static Encoding, u64)> {
+    encoding::file_encoding(path, rules.encoding.or_else(|| config.encoding_for(path)))
+}
+
+
 /// Whether to print the file header line for this event. We show it when the
 /// file is read from its start, or when the previously printed file differs
 /// (this limits header spam for consecutive appends to the same file).
@@ -344,8 +823,11 @@ fn handle_file_event(
     file_position: u64,
     file_size: u64,
     file_path: &str,
+    state: &FileState,
     last_file: &mut String,
+    config: &Config,
 ) {
+    let rules = &state.rules;
     debug!(
         "Watched file position: {}, file size: {}, file name: {}",
         format!("{file_position}").cyan(),
@@ -359,20 +841,153 @@ fn handle_file_event(
         format!("@{file_position}").black()
     );
 
+    if !rules.show {
+        trace!("{}: {}", "-Hidden".magenta(), file_path.cyan());
+        return;
+    }
+
+    // files selected for hexdumps show their new bytes as such, binary or not
+    if config.is_hexdump_file(Path::new(file_path)) {
+        let content = (file_position < file_size).then(|| {
+            read_hexdump(
+                Path::new(file_path),
+                file_position,
+                file_size,
+                config.hexdump_bytes.unwrap_or_default(),
+            )
+            .unwrap_or_else(|error_cause| {
+                error!(
+                    "Couldn't read file: {}. Caused by: {}",
+                    file_path.cyan(),
+                    error_cause.to_string().red()
+                );
+                vec![]
+            })
+        });
+        print_file_content(file_position, file_path, content, rules, last_file);
+        return;
+    }
+
+    // binary files are only summarized (or not shown at all); reading their
+    // bytes as lines would print garbage
+    if state.binary {
+        if config.binary_files == Some(BinaryFiles::Skip) {
+            trace!("{}: {}", "-Binary".magenta(), file_path.cyan());
+            return;
+        }
+        let summary = (file_position < file_size).then(|| {
+            if file_position == 0 {
+                vec![format!("* binary file, {} *", format_size(file_size))]
+            } else {
+                vec![format!(
+                    "* binary file grew by {} *",
+                    format_size(file_size - file_position)
+                )]
+            }
+        });
+        print_file_content(file_position, file_path, summary, rules, last_file);
+        return;
+    }
+
+    // Byte offsets of a compressed file mean nothing in its decompressed
+    // stream, so it shows its decompressed tail instead, once: as soon as the
+    // stream (still being written, maybe) decodes to its end.
+    if let Some(compression) = state.compression {
+        if state.decompressed.get() {
+            trace!("{}: {}", "-Decompressed".magenta(), file_path.cyan());
+            return;
+        }
+        let Some(lines) = read_decompressed_tail(
+            file_path,
+            compression,
+            rules.tail_bytes.or(config.tail_bytes).unwrap_or_default(),
+        ) else {
+            return;
+        };
+        state.decompressed.set(true);
+        print_text_content(
+            file_position,
+            file_path,
+            Some(lines),
+            rules,
+            || file_syntax(file_path, state, config),
+            last_file,
+        );
+        return;
+    }
+
+    // print content of the file that triggered the event
+    let content = (file_position < file_size).then(|| {
+        match state.encoding {
+            Some((encoding, bom_length)) => {
+                read_decoded_lines(Path::new(file_path), file_position, encoding, bom_length)
+                    .unwrap_or_else(|error_cause| {
+                        error!(
+                            "Couldn't read file: {}. Caused by: {}",
+                            file_path.cyan(),
+                            error_cause.to_string().red()
+                        );
+                        vec![]
+                    })
+            }
+            None => seek_file_to_position_and_read(file_path, file_position),
+        }
+    });
+    print_text_content(
+        file_position,
+        file_path,
+        content,
+        rules,
+        || file_syntax(file_path, state, config),
+        last_file,
+    );
+}
+
+
+/// [`print_file_content`] of text read from a file, keeping only the lines
+/// passing the line filters of its `rules`, highlighted as its `syntax`
+/// (looked up only when there are lines to highlight).
+/// Nothing is printed, not even the header, when the filters let none through.
+fn print_text_content(
+    file_position: u64,
+    file_path: &str,
+    content: Option<Vec<String>>,
+    rules: &FileRules,
+    syntax: impl FnOnce() -> Option<usize>,
+    last_file: &mut String,
+) {
+    let content = content.map(|lines| rules.filter_lines(lines));
+    if rules.filters_lines() && content.as_ref().is_some_and(Vec::is_empty) {
+        trace!("{}: {}", "-Filtered".magenta(), file_path.cyan());
+        return;
+    }
+    let content = content.map(|lines| render_content(file_path, lines, syntax()));
+    print_file_content(file_position, file_path, content, rules, last_file);
+}
+
+
+/// Print the file header line (when due, see [`should_print_header`]) followed
+/// by the `content` as is, and remember `file_path` as the last printed file.
+/// The header is labeled and colored as the file's `rules` say.
+fn print_file_content(
+    file_position: u64,
+    file_path: &str,
+    content: Option<Vec<String>>,
+    rules: &FileRules,
+    last_file: &mut String,
+) {
     if should_print_header(file_position, last_file, file_path) {
         println!();
         println!(); // just start new entry after \n\n
         info!(
             "{} {}",
-            file_path.blue(),
+            rules.header(file_path),
             format!("@{file_position}").black()
         );
     }
 
-    // print content of the file that triggered the event
-    if file_position < file_size {
-        let content = seek_file_to_position_and_read(file_path, file_position);
-        println!("{}", render_content(file_path, content).join("\n"));
+    if let Some(content) = content {
+        println!("{}", content.join("\n"));
     }
 
     // Remember the last file we printed; only rewrite the buffer when it
@@ -384,18 +999,41 @@ fn handle_file_event(
 }
 
 
-/// Syntax-highlight file content for terminal output, keyed on the file
-/// extension. When stdout is not a terminal (piped / redirected) the raw lines
-/// are returned unchanged, so captured output stays free of ANSI escapes.
-fn render_content(file_path: &str, lines: Vec<String>) -> Vec<String> {
-    if !std::io::stdout().is_terminal() {
+/// Print lines read from a stream source (such as a spawned command's output)
+/// the way appended file content is printed, under the stream's label.
+pub fn print_stream_chunk(chunk: StreamChunk, last_file: &mut String) {
+    trace!(
+        "{}: {} {}",
+        "+StreamHandle".magenta(),
+        chunk.label.cyan(),
+        format!("@{}", chunk.position).black()
+    );
+    print_file_content(
+        chunk.position,
+        &chunk.label,
+        Some(render_content(&chunk.label, chunk.lines, None)),
+        &FileRules::default(),
+        last_file,
+    );
+}
+
+
+/// Syntax-highlight file content for terminal output, keyed on the `syntax`
+/// detected for the file or else its extension. When stdout is not a terminal
+/// (piped / redirected) the raw lines are returned unchanged, so captured
+/// output stays free of ANSI escapes.
+fn render_content(file_path: &str, lines: Vec<String>, syntax: Option<usize>) -> Vec<String> {
+    if !io::stdout().is_terminal() {
         return lines;
     }
-    let extension = Path::new(file_path)
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or_default();
-    crate::highlight::highlighter().highlight(extension, &lines)
+    let highlighter = highlighter();
+    let syntax = syntax.or_else(|| {
+        Path::new(file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|extension| highlighter.find(extension))
+    });
+    highlighter.highlight(syntax, &lines)
 }
 
 
@@ -426,22 +1064,157 @@ fn seek_file_to_position_and_read(file_to_watch: &str, file_position: u64) -> Ve
 }
 
 
+/// Read the last `tail_lines` lines of a file, decompressing it if needed.
+/// Returns the byte offset the lines start at (0 for compressed files, whose
+/// offsets have no meaning) together with the lines.
+fn read_last_lines(
+    file_path: &str,
+    file_size: u64,
+    tail_lines: usize,
+    compression: Option<Compression>,
+) -> (u64, Vec<String>) {
+    let lines = match compression {
+        Some(compression) => {
+            crate::compression::decompressed_last_lines(
+                Path::new(file_path),
+                compression,
+                tail_lines,
+            )
+            .map(|lines| (0, lines))
+        }
+        None => {
+            File::open(file_path)
+                .and_then(|mut file| last_lines_offset(&mut file, file_size, tail_lines))
+                .map(|offset| {
+                    if offset < file_size {
+                        (offset, seek_file_to_position_and_read(file_path, offset))
+                    } else {
+                        (offset, vec![])
+                    }
+                })
+        }
+    };
+    lines.unwrap_or_else(|error_cause| {
+        if compression.is_some() && error_cause.kind() == io::ErrorKind::UnexpectedEof {
+            debug!(
+                "Compressed file not complete yet: {}. Caused by: {}",
+                file_path.cyan(),
+                error_cause.to_string().red()
+            );
+        } else {
+            error!(
+                "Couldn't read tail of file: {}. Caused by: {}",
+                file_path.cyan(),
+                error_cause.to_string().red()
+            );
+        }
+        (0, vec![])
+    })
+}
+
+
+/// Byte offset at which the last `tail_lines` lines of a `file_size` long file
+/// start. Scans backwards from EOF in fixed blocks, so only the tail of the
+/// file is ever read. A trailing newline at EOF doesn't start an empty line.
+fn last_lines_offset(file: &mut File, file_size: u64, tail_lines: usize) -> io::Result<u64> {
+    if tail_lines == 0 {
+        return Ok(file_size);
+    }
+    let mut block = [0u8; TAIL_SCAN_BLOCK];
+    let mut block_start = file_size;
+    let mut newlines = 0;
+    while block_start > 0 {
+        let block_len = block_start.min(TAIL_SCAN_BLOCK as u64) as usize;
+        block_start -= block_len as u64;
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(&mut block[..block_len])?;
+        for (index, byte) in block[..block_len].iter().enumerate().rev() {
+            let line_start = block_start + index as u64 + 1;
+            if *byte == b'\n' && line_start != file_size {
+                newlines += 1;
+                if newlines == tail_lines {
+                    return Ok(line_start);
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+
+/// Decompress a compressed file and return the lines of the last `tail_bytes`
+/// of its decompressed content. `None` when it can't be decompressed, or not
+/// yet: a stream that ends early is still being written.
+fn read_decompressed_tail(
+    file_to_watch: &str,
+    compression: Compression,
+    tail_bytes: u64,
+) -> Option<Vec<String>> {
+    match crate::compression::decompressed_tail(
+        Path::new(file_to_watch),
+        compression,
+        tail_bytes,
+    ) {
+        Ok(tail) => {
+            let lines_out: Vec<_> = tail.lines().map_while(Result::ok).collect();
+            trace!(
+                "Decompressed lines out: '{}'",
+                format!("{lines_out:?}").cyan()
+            );
+            if lines_out.is_empty() {
+                Some(vec![String::from("* binary file modification *")])
+            } else {
+                Some(lines_out)
+            }
+        }
+
+        Err(error_cause) if error_cause.kind() == io::ErrorKind::UnexpectedEof => {
+            debug!(
+                "Compressed file not complete yet: {}. Caused by: {}",
+                file_to_watch.cyan(),
+                error_cause.to_string().red()
+            );
+            None
+        }
+
+        Err(error_cause) => {
+            error!(
+                "Couldn't decompress file: {}. Caused by: {}",
+                file_to_watch.cyan(),
+                error_cause.to_string().red()
+            );
+            None
+        }
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::{
-        decide_read_position, is_ignored, matches_glob, seek_file_to_position_and_read,
-        should_print_header,
+        decide_read_position, is_ignored, is_included, last_lines_offset, matches_glob,
+        print_tails, process_exists, process_file_event, read_decompressed_tail,
+        read_last_lines, seek_file_to_position_and_read, should_print_header, sniffed_state,
+        sorted_files, walkdir_recursive,
     };
+    use crate::cli::TailOrder;
+    use crate::compression::Compression;
+    use crate::config::Config;
     use crate::consts::DEFAULT_IGNORE_PATTERNS;
+    use crate::glob::Glob;
+    use crate::types::{FileAndPosition, FileState};
+    use crate::watcher::KqueueWatcher;
     use std::fs;
+    use std::io::Write;
     use std::path::Path;
+    use std::sync::Arc;
 
-    /// The built-in ignore patterns precompiled to char slices (as a live
-    /// `Config` holds them via `ignore_globs()`).
-    fn default_globs() -> Vec<Vec<char>> {
+    /// The built-in ignore patterns precompiled to globs (as a live `Config`
+    /// holds them via `ignore_globs()`).
+    fn default_globs() -> Vec<Glob> {
         DEFAULT_IGNORE_PATTERNS
             .iter()
-            .map(|pattern| pattern.chars().collect())
+            .map(|pattern| Glob::new(pattern))
             .collect()
     }
 
@@ -479,20 +1252,29 @@ mod tests {
     fn append_continues_from_last_offset() {
         // Same inode, file grew: resume from the previous end -> only the newly
         // appended bytes are shown.
-        assert_eq!(decide_read_position(Some((1, 24)), 1, 35, TAIL_BYTES), 24);
+        assert_eq!(
+            decide_read_position(Some(&FileState::new(1, 24)), 1, 35, TAIL_BYTES),
+            24
+        );
     }
 
     #[test]
     fn no_growth_returns_end_so_nothing_is_reprinted() {
         // Same inode, size unchanged: position == size, so the caller's
         // `position < size` guard prints nothing (no duplicate output).
-        assert_eq!(decide_read_position(Some((1, 35)), 1, 35, TAIL_BYTES), 35);
+        assert_eq!(
+            decide_read_position(Some(&FileState::new(1, 35)), 1, 35, TAIL_BYTES),
+            35
+        );
     }
 
     #[test]
     fn truncate_in_place_smaller_rereads_from_start() {
         // Same inode but the cursor is now past EOF -> file was truncated.
-        assert_eq!(decide_read_position(Some((1, 800)), 1, 23, TAIL_BYTES), 0);
+        assert_eq!(
+            decide_read_position(Some(&FileState::new(1, 800)), 1, 23, TAIL_BYTES),
+            0
+        );
     }
 
     #[test]
@@ -500,20 +1282,29 @@ mod tests {
         // Same inode, still growing past the old cursor: like `tail -F`, we
         // can't tell an in-place rewrite from an append, so we resume from the
         // old offset. Documented limitation.
-        assert_eq!(decide_read_position(Some((1, 24)), 1, 48, TAIL_BYTES), 24);
+        assert_eq!(
+            decide_read_position(Some(&FileState::new(1, 24)), 1, 48, TAIL_BYTES),
+            24
+        );
     }
 
     #[test]
     fn replaced_file_new_inode_rereads_from_start() {
         // Atomic rename / rotation: same path, different inode -> read the
         // whole new file from the beginning.
-        assert_eq!(decide_read_position(Some((1, 800)), 2, 40, TAIL_BYTES), 0);
+        assert_eq!(
+            decide_read_position(Some(&FileState::new(1, 800)), 2, 40, TAIL_BYTES),
+            0
+        );
     }
 
     #[test]
     fn append_after_replace_continues_from_offset() {
         // Once the new inode is recorded, subsequent appends resume normally.
-        assert_eq!(decide_read_position(Some((2, 40)), 2, 63, TAIL_BYTES), 40);
+        assert_eq!(
+            decide_read_position(Some(&FileState::new(2, 40)), 2, 63, TAIL_BYTES),
+            40
+        );
     }
 
     // ---- should_print_header: when to emit the file header line ----
@@ -567,9 +1358,14 @@ mod tests {
         let globs = default_globs();
         assert!(is_ignored(
             Path::new("./src/log_watcher.rs.tmp.29966.0e8daadcf5e2"),
+            false,
+            &globs
+        ));
+        assert!(!is_ignored(
+            Path::new("./src/log_watcher.rs"),
+            false,
             &globs
         ));
-        assert!(!is_ignored(Path::new("./src/log_watcher.rs"), &globs));
     }
 
     #[test]
@@ -577,15 +1373,50 @@ mod tests {
         let globs = default_globs();
         for name in ["notes.txt~", ".main.rs.swp", "patch.orig", "data.bak"] {
             assert!(
-                is_ignored(Path::new(name), &globs),
+                is_ignored(Path::new(name), false, &globs),
                 "should ignore {name}"
             );
         }
     }
 
+    #[test]
+    fn excluded_directory_covers_everything_below_it() {
+        let globs: Vec<Glob> = ["node_modules", ".git/", "**/cache/**"]
+            .iter()
+            .map(|pattern| Glob::new(pattern))
+            .collect();
+        assert!(is_ignored(Path::new("node_modules"), true, &globs));
+        assert!(is_ignored(Path::new(".git"), true, &globs));
+        assert!(is_ignored(
+            Path::new("web/node_modules/x/y.log"),
+            false,
+            &globs
+        ));
+        assert!(is_ignored(Path::new("app/cache/entry.log"), false, &globs));
+        assert!(!is_ignored(Path::new("app/logs/app.log"), false, &globs));
+    }
+
+    #[test]
+    fn include_patterns_select_files_unless_ignored() {
+        let includes = vec![Glob::new("*.log"), Glob::new("*.err")];
+        assert!(is_included(Path::new("app.log"), &includes));
+        assert!(is_included(Path::new("nested/worker.err"), &includes));
+        assert!(!is_included(Path::new("data.db"), &includes));
+        assert!(is_included(Path::new("data.db"), &[]));
+        assert!(
+            is_included(Path::new(""), &includes),
+            "roots are always watched"
+        );
+        assert!(is_ignored(
+            Path::new("app.log.tmp"),
+            false,
+            &default_globs()
+        ));
+    }
+
     #[test]
     fn empty_patterns_ignore_nothing() {
-        assert!(!is_ignored(Path::new("foo.rs.tmp.1.2"), &[]));
+        assert!(!is_ignored(Path::new("foo.rs.tmp.1.2"), false, &[]));
     }
 
     // ---- seek_file_to_position_and_read: content extraction ----
@@ -632,6 +1463,288 @@ mod tests {
         assert!(seek_file_to_position_and_read(&path, 0).is_empty());
     }
 
+    #[test]
+    fn compressed_file_yields_decompressed_lines() {
+        let path = temp_path("rotated.log.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"rotated one\nrotated two\n").unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+        assert_eq!(
+            read_decompressed_tail(&path, Compression::Gzip, 1024),
+            Some(vec!["rotated one".to_string(), "rotated two".to_string()])
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn compressed_file_written_in_two_parts_is_shown_once_complete() {
+        let path = temp_path("written.log.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"written one\nwritten two\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        fs::write(&path, first).unwrap();
+        assert_eq!(read_decompressed_tail(&path, Compression::Gzip, 1024), None);
+
+        let config = Config::default();
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        let mut states = FileAndPosition::new();
+        let mut last_file = String::new();
+        process_file_event(&path, &mut watcher, &mut states, &mut last_file, &config);
+        assert!(!states[&path].decompressed.get(), "not complete yet");
+        assert!(last_file.is_empty(), "nothing shown");
+
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(second)
+            .unwrap();
+        process_file_event(&path, &mut watcher, &mut states, &mut last_file, &config);
+        assert_eq!(states[&path].compression, Some(Compression::Gzip));
+        assert!(states[&path].decompressed.get(), "shown once complete");
+        assert_eq!(last_file, path);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn files_without_lines_have_no_tail_shown() {
+        let empty = temp_path("tail_empty.log");
+        let full = temp_path("tail_full.log");
+        fs::write(&empty, "").unwrap();
+        fs::write(&full, "a line\n").unwrap();
+        let config = Config::default();
+        let mut states = FileAndPosition::new();
+        states.insert(empty.clone(), FileState::new(1, 0));
+        let mut last_file = String::new();
+        print_tails(&states, &mut last_file, &config, TailOrder::Path, 0);
+        assert!(last_file.is_empty(), "nothing shown for an empty file");
+        states.insert(full.clone(), FileState::new(2, 7));
+        print_tails(&states, &mut last_file, &config, TailOrder::Path, 0);
+        assert_eq!(last_file, full);
+        let _ = fs::remove_file(&empty);
+        let _ = fs::remove_file(&full);
+    }
+
+    // ---- process_exists: --pid fallback ----
+
+    #[test]
+    fn own_process_exists_and_bogus_pid_does_not() {
+        assert!(process_exists(std::process::id() as i32));
+        assert!(!process_exists(i32::MAX));
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id() as i32;
+        child.wait().unwrap();
+        assert!(!process_exists(pid), "reaped child is gone");
+    }
+
+    // ---- sorted_files: one-shot output order ----
+
+    #[test]
+    fn one_shot_files_sorted_by_path() {
+        let mut states = FileAndPosition::new();
+        states.insert("b.log".to_string(), FileState::new(1, 20));
+        states.insert("a.log".to_string(), FileState::new(2, 10));
+        assert_eq!(
+            sorted_files(&states, TailOrder::Path),
+            vec![("a.log".to_string(), 10), ("b.log".to_string(), 20)]
+        );
+    }
+
+    #[test]
+    fn one_shot_files_sorted_by_mtime_most_recent_last() {
+        let older = temp_path("mtime_older");
+        let newer = temp_path("mtime_newer");
+        fs::write(&older, "old\n").unwrap();
+        fs::write(&newer, "new\n").unwrap();
+        let now = std::time::SystemTime::now();
+        fs::File::options()
+            .write(true)
+            .open(&older)
+            .unwrap()
+            .set_modified(now - std::time::Duration::from_secs(3600))
+            .unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&newer)
+            .unwrap()
+            .set_modified(now)
+            .unwrap();
+        // Name the newer file so it sorts first by path, to tell the orders apart.
+        let mut states = FileAndPosition::new();
+        states.insert(newer.clone(), FileState::new(1, 4));
+        states.insert(older.clone(), FileState::new(2, 4));
+        let order: Vec<String> = sorted_files(&states, TailOrder::Mtime)
+            .into_iter()
+            .map(|(file_path, _)| file_path)
+            .collect();
+        assert_eq!(order, vec![older.clone(), newer.clone()]);
+        let _ = fs::remove_file(&older);
+        let _ = fs::remove_file(&newer);
+    }
+
+    // ---- last_lines_offset / read_last_lines: initial tails ----
+
+    #[test]
+    fn last_lines_offset_skips_trailing_newline() {
+        let path = temp_path("last_lines");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let mut file = fs::File::open(&path).unwrap();
+        assert_eq!(last_lines_offset(&mut file, 14, 1).unwrap(), 8);
+        assert_eq!(last_lines_offset(&mut file, 14, 2).unwrap(), 4);
+        assert_eq!(last_lines_offset(&mut file, 14, 10).unwrap(), 0);
+        assert_eq!(last_lines_offset(&mut file, 14, 0).unwrap(), 14);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn last_lines_span_multiple_scan_blocks() {
+        let path = temp_path("last_lines_blocks");
+        let content: String = (0..5000).map(|n| format!("line {n}\n")).collect();
+        fs::write(&path, &content).unwrap();
+        let (_, lines) = read_last_lines(&path, content.len() as u64, 3, None);
+        assert_eq!(lines, vec!["line 4997", "line 4998", "line 4999"]);
+        let (position, lines) = read_last_lines(&path, content.len() as u64, 5000, None);
+        assert_eq!(position, 0);
+        assert_eq!(lines.len(), 5000);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn symlinked_files_are_watched_without_following_links() {
+        let directory = Path::new(&temp_path("symlinks")).to_path_buf();
+        let target = temp_path("symlink_target.txt");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(&target, "line\n").unwrap();
+        let link = directory.join("linked.txt");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let mut config = Config::default();
+        config.follow_links = Some(false);
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        let mut states = FileAndPosition::new();
+        walkdir_recursive(
+            &mut watcher,
+            &mut states,
+            &mut String::new(),
+            &directory,
+            &config,
+            true,
+        );
+        assert!(states.contains_key(link.to_string_lossy().as_ref()));
+        assert!(watcher.is_registered(&link));
+        let _ = fs::remove_dir_all(&directory);
+        let _ = fs::remove_file(&target);
+    }
+
+    #[test]
+    fn hidden_files_are_not_watched() {
+        let directory = Path::new(&temp_path("hidden")).to_path_buf();
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("app.log"), "line\n").unwrap();
+        fs::write(directory.join("noise.txt"), "line\n").unwrap();
+        let mut config = Config::default();
+        config.profiles = Some(
+            ron::from_str(
+                r#"{"quiet": (rules: Some([(pattern: "*.txt", show: Some(false))]))}"#,
+            )
+            .unwrap(),
+        );
+        config.apply_profile("quiet").unwrap();
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        let mut states = FileAndPosition::new();
+        walkdir_recursive(
+            &mut watcher,
+            &mut states,
+            &mut String::new(),
+            &directory,
+            &config,
+            true,
+        );
+        let (shown, hidden) = (directory.join("app.log"), directory.join("noise.txt"));
+        assert!(states.contains_key(shown.to_string_lossy().as_ref()));
+        assert!(!states.contains_key(hidden.to_string_lossy().as_ref()));
+        assert!(watcher.is_registered(&shown));
+        assert!(!watcher.is_registered(&hidden));
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn files_ignored_after_an_ignore_file_change_are_unwatched() {
+        let root = Path::new(&temp_path("ignored_later")).to_path_buf();
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("debug.log"), "line\n").unwrap();
+        fs::write(root.join("notes.txt"), "line\n").unwrap();
+        let mut config = Config::default();
+        config.ignore_files = Some(true);
+        config.set_roots(&[root.to_string_lossy().to_string()]);
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        let mut states = FileAndPosition::new();
+        let mut last_file = String::new();
+        walkdir_recursive(
+            &mut watcher,
+            &mut states,
+            &mut last_file,
+            &root,
+            &config,
+            true,
+        );
+        assert!(watcher.is_registered(&root.join("debug.log")));
+
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        process_file_event(
+            &root.join(".gitignore").to_string_lossy(),
+            &mut watcher,
+            &mut states,
+            &mut last_file,
+            &config,
+        );
+        let debug_log = root.join("debug.log");
+        assert!(!watcher.is_registered(&debug_log));
+        assert!(!states.contains_key(debug_log.to_string_lossy().as_ref()));
+        assert!(watcher.is_registered(&root.join("notes.txt")));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn compression_is_sniffed_once_with_the_state() {
+        let path = temp_path("sniffed.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"rotated\n").unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+        let config = Config::default();
+        let state = sniffed_state(Path::new(&path), 1, 0, Arc::default(), &config);
+        assert_eq!(state.compression, Some(Compression::Gzip));
+        assert!(
+            !state.binary,
+            "compressed files are decompressed, not summarized"
+        );
+        assert!(
+            state.syntax.get().is_none(),
+            "the syntax is detected on the first read"
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn last_lines_of_compressed_file_are_decompressed() {
+        let path = temp_path("last_lines.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"first\nsecond\nthird\n").unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+        assert_eq!(
+            read_last_lines(&path, 0, 2, Some(Compression::Gzip)),
+            (0, vec!["second".to_string(), "third".to_string()])
+        );
+        let _ = fs::remove_file(&path);
+    }
+
     /// End-to-end of the reported bug: a large file gets rewritten to a smaller
     /// size, so the stored cursor is past the new EOF. We must reset to 0 and
     /// read the full new content (previously produced a header with no body).
@@ -639,11 +1752,11 @@ mod tests {
     fn truncated_rewrite_shows_full_new_content() {
         let path = temp_path("truncate_flow");
         // Pretend we had watched ~800 bytes of old content.
-        let previous = Some((1u64, 800u64));
+        let previous = FileState::new(1, 800);
         // File is rewritten in place (same inode) to something small.
         fs::write(&path, "NEW SMALL CONTENT LINE\n").unwrap();
         let new_size = fs::metadata(&path).unwrap().len();
-        let position = decide_read_position(previous, 1, new_size, TAIL_BYTES);
+        let position = decide_read_position(Some(&previous), 1, new_size, TAIL_BYTES);
         assert_eq!(position, 0, "truncation must reset the cursor to the start");
         assert_eq!(
             seek_file_to_position_and_read(&path, position),
@@ -0,0 +1,522 @@
+//! Kqueue watcher that remembers what it has registered.
+//!
+//! Re-adding a vnode watch costs a close/open pair plus a resubmission of the
+//! whole watch list, and a directory re-walk used to do that for every file in
+//! the tree. [`KqueueWatcher`] tracks each registered path with the inode its
+//! descriptor refers to, so only genuinely new paths (or paths replaced by a
+//! new inode) are registered, and the watch list is only resubmitted after it
+//! changed.
+//!
+//! Every watch holds a descriptor, so the number of watches is capped by a
+//! budget. Once it is used up, the least recently active file is evicted: its
+//! watch is dropped and the file is polled at a low rate instead, until it
+//! changes and is promoted back to a watch. Directories are never evicted, as
+//! their events are the only way new files are noticed.
+
+use colored::Colorize;
+use kqueue2::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+    path::Path,
+    time::Duration,
+};
+
+
+/// kqueue flags, from: /usr/include/sys/event.h
+/// NOTE_DELETE     0x00000001              /* vnode was removed */
+/// NOTE_WRITE      0x00000002              /* data contents changed */
+/// NOTE_EXTEND     0x00000004              /* size increased */
+/// NOTE_ATTRIB     0x00000008              /* attributes changed */
+/// NOTE_LINK       0x00000010              /* link count changed */
+/// NOTE_RENAME     0x00000020              /* vnode was renamed */
+/// NOTE_REVOKE     0x00000040              /* vnode access was revoked */
+fn vnode_flags() -> FilterFlag {
+    NOTE_WRITE | NOTE_LINK | NOTE_RENAME | NOTE_DELETE | NOTE_EXTEND // | NOTE_ATTRIB
+    // | NOTE_REVOKE
+}
+
+
+/// A registered path: the inode its descriptor refers to, and for files the
+/// tick of its latest activity (directories are never evicted, so they have
+/// none).
+#[derive(Debug, Clone, Copy)]
+struct Registration {
+    inode: u64,
+    last_active: Option<u64>,
+}
+
+
+/// A kqueue [`Watcher`] plus the paths (and their inodes) it holds watches for.
+#[derive(Debug)]
+pub struct KqueueWatcher {
+    watcher: Watcher,
+    registered: HashMap<String, Registration>,
+    changed: bool,
+
+    /// Most watches held at once, `0` for no limit.
+    budget: usize,
+
+    /// Watched files by the tick of their latest activity, least recent first.
+    activity: BTreeMap<u64, String>,
+    clock: u64,
+
+    /// Files that don't fit into the budget, polled instead of watched, with
+    /// the inode they referred to.
+    evicted: HashMap<String, u64>,
+    evictions: u64,
+}
+
+
+/// Snapshot of the watch budget usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchStatus {
+    /// Watched files.
+    pub files: usize,
+
+    /// Watched directories.
+    pub directories: usize,
+
+    /// Most watches held at once, `0` for no limit.
+    pub budget: usize,
+
+    /// Files polled because they didn't fit into the budget.
+    pub polled: usize,
+
+    /// Evictions since start.
+    pub evictions: u64,
+}
+
+
+impl fmt::Display for WatchStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let budget = if self.budget == 0 {
+            "unlimited".to_string()
+        } else {
+            self.budget.to_string()
+        };
+        write!(
+            f,
+            "watches: {}/{} ({} files, {} directories), polled: {}, evictions: {}",
+            self.files + self.directories,
+            budget,
+            self.files,
+            self.directories,
+            self.polled,
+            self.evictions
+        )
+    }
+}
+
+
+impl KqueueWatcher {
+    /// Create a watcher with nothing registered yet, holding at most `budget`
+    /// watches (`0` for no limit).
+    pub fn new(budget: usize) -> io::Result<Self> {
+        Ok(KqueueWatcher {
+            watcher: Watcher::new()?,
+            registered: HashMap::new(),
+            changed: false,
+            budget,
+            activity: BTreeMap::new(),
+            clock: 0,
+            evicted: HashMap::new(),
+            evictions: 0,
+        })
+    }
+
+
+    /// Make sure `path` is watched by a descriptor for `inode`. Does nothing
+    /// when it already is, or when it's polled for the same inode (it's only
+    /// [`KqueueWatcher::promote`]d once it changed); re-registers it when the
+    /// path now refers to a different inode (atomic rename, rotation). When
+    /// the budget is used up, the least recently active file is evicted to
+    /// make room; if there is none, a file is polled instead of watched.
+    /// Returns whether a watch was added.
+    pub fn register(&mut self, path: &Path, inode: u64, is_file: bool) -> io::Result<bool> {
+        let key = path.to_string_lossy();
+        if self.evicted.get(key.as_ref()) == Some(&inode) {
+            return Ok(false);
+        }
+        match self.registered.get(key.as_ref()) {
+            Some(registration) if registration.inode == inode => return Ok(false),
+            Some(_) => self.unregister(path),
+            None => {}
+        }
+        if self.budget > 0 && self.registered.len() >= self.budget && !self.evict() && is_file
+        {
+            debug!(
+                "Watch budget used up, polling: {}",
+                format!("{path:?}").cyan()
+            );
+            self.evicted.insert(key.into_owned(), inode);
+            return Ok(false);
+        }
+        self.watcher
+            .add_filename(path, EventFilter::EVFILT_VNODE, vnode_flags())?;
+        trace!("{}: {}", "+Watch".magenta(), format!("{path:?}").cyan());
+        let last_active = is_file.then(|| self.tick(&key));
+        self.evicted.remove(key.as_ref());
+        self.registered.insert(
+            key.into_owned(),
+            Registration {
+                inode,
+                last_active,
+            },
+        );
+        self.changed = true;
+        Ok(true)
+    }
+
+
+    /// Watch the polled file `path`, now referring to `inode`, again after it
+    /// changed (see [`KqueueWatcher::register`]).
+    pub fn promote(&mut self, path: &Path, inode: u64) -> io::Result<bool> {
+        self.evicted.remove(path.to_string_lossy().as_ref());
+        self.register(path, inode, true)
+    }
+
+
+    /// Note activity on the watched file `path`, moving it to the back of the
+    /// eviction queue.
+    pub fn touch(&mut self, path: &str) {
+        if let Some(last_active) = self
+            .registered
+            .get(path)
+            .and_then(|registration| registration.last_active)
+        {
+            self.activity.remove(&last_active);
+            let now = self.tick(path);
+            if let Some(registration) = self.registered.get_mut(path) {
+                registration.last_active = Some(now);
+            }
+        }
+    }
+
+
+    /// Next activity tick, with `path` recorded under it.
+    fn tick(&mut self, path: &str) -> u64 {
+        self.clock += 1;
+        self.activity.insert(self.clock, path.to_string());
+        self.clock
+    }
+
+
+    /// Drop the watch on the least recently active file and poll it instead.
+    /// Returns `false` when no file is watched.
+    fn evict(&mut self) -> bool {
+        let Some((_, path)) = self.activity.pop_first() else {
+            return false;
+        };
+        let Some(registration) = self.registered.remove(&path) else {
+            return false;
+        };
+        self.delete(&path);
+        self.evictions += 1;
+        debug!("{}: {}, {}", "-Evict".magenta(), path.cyan(), self.status());
+        self.evicted.insert(path, registration.inode);
+        true
+    }
+
+
+    /// Drop the watch on `path`, if any, or stop polling it.
+    pub fn unregister(&mut self, path: &Path) {
+        let key = path.to_string_lossy();
+        self.evicted.remove(key.as_ref());
+        if let Some(registration) = self.registered.remove(key.as_ref()) {
+            if let Some(last_active) = registration.last_active {
+                self.activity.remove(&last_active);
+            }
+            self.delete(&key);
+            trace!("{}: {}", "-Watch".magenta(), format!("{path:?}").cyan());
+        }
+    }
+
+
+    /// Drop the watch on `path` from the watch list, and close its descriptor.
+    fn delete(&mut self, path: &str) {
+        // watches added since the last submission have no kevent to delete yet
+        self.watcher
+            .remove_filename(path, EventFilter::EVFILT_VNODE)
+            .or_else(|error| {
+                match error.kind() {
+                    io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(error),
+                }
+            })
+            .unwrap_or_else(|error| {
+                error!(
+                    "Could not remove watch on file: {:?}. Error cause: {}",
+                    path,
+                    error.to_string().red()
+                )
+            });
+    }
+
+
+    /// Whether `path` is currently watched.
+    pub fn is_registered(&self, path: &Path) -> bool {
+        self.registered
+            .contains_key(path.to_string_lossy().as_ref())
+    }
+
+
+    /// Whether `path` is currently watched by a descriptor for `inode`.
+    pub fn is_registered_as(&self, path: &Path, inode: u64) -> bool {
+        self.registered
+            .get(path.to_string_lossy().as_ref())
+            .is_some_and(|registration| registration.inode == inode)
+    }
+
+
+    /// Number of watched paths (files and directories).
+    pub fn len(&self) -> usize {
+        self.registered.len()
+    }
+
+
+    /// Whether nothing is watched.
+    pub fn is_empty(&self) -> bool {
+        self.registered.is_empty()
+    }
+
+
+    /// Watched paths and files polled instead, sorted.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .registered
+            .keys()
+            .chain(self.evicted.keys())
+            .cloned()
+            .collect();
+        paths.sort();
+        paths
+    }
+
+
+    /// Files polled instead of watched, sorted.
+    pub fn evicted(&self) -> Vec<String> {
+        let mut evicted: Vec<String> = self.evicted.keys().cloned().collect();
+        evicted.sort();
+        evicted
+    }
+
+
+    /// Current usage of the watch budget.
+    pub fn status(&self) -> WatchStatus {
+        let files = self.activity.len();
+        WatchStatus {
+            files,
+            directories: self.registered.len() - files,
+            budget: self.budget,
+            polled: self.evicted.len(),
+            evictions: self.evictions,
+        }
+    }
+
+
+    /// Add an exit watch on process `pid`, submitted right away: a process
+    /// that's already gone fails here (with `ESRCH`) and is dropped, instead of
+    /// failing every later submission of the watch list.
+    pub fn add_pid(&mut self, pid: i32) -> io::Result<()> {
+        self.watcher
+            .add_pid(pid, EventFilter::EVFILT_PROC, NOTE_EXIT)?;
+        self.changed = true;
+        self.watch().inspect_err(|_| self.remove_pid(pid))
+    }
+
+
+    /// Drop the exit watch on process `pid`, once it exited.
+    pub fn remove_pid(&mut self, pid: i32) {
+        // kqueue drops the watch of an exited process by itself, so only the
+        // watch list is cleaned up; the rest of it is submitted again, as a
+        // failed submission stops at its first error
+        self.watcher
+            .remove_pid(pid, EventFilter::EVFILT_PROC)
+            .unwrap_or_default();
+        self.changed = true;
+    }
+
+
+    /// Submit the watch list to kqueue, but only if it changed since the last
+    /// submission.
+    pub fn watch(&mut self) -> io::Result<()> {
+        if self.changed {
+            trace!("{}: watch()", "+Trigger".magenta());
+            self.watcher.watch()?;
+            self.changed = false;
+        }
+        Ok(())
+    }
+
+
+    /// Wait up to `timeout` for the next event (`None` returns immediately).
+    pub fn poll(&self, timeout: Option<Duration>) -> Option<Event> {
+        self.watcher.poll(timeout)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{KqueueWatcher, WatchStatus};
+    use std::{fs, os::unix::fs::MetadataExt, path::PathBuf};
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "lw_watcher_test_{}_{}",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, "content\n").unwrap();
+        path
+    }
+
+    #[test]
+    fn registering_the_same_inode_twice_is_a_no_op() {
+        let path = temp_file("same_inode");
+        let inode = fs::metadata(&path).unwrap().ino();
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        assert!(watcher.register(&path, inode, true).unwrap());
+        assert!(!watcher.register(&path, inode, true).unwrap());
+        assert_eq!(watcher.len(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn new_inode_is_registered_again() {
+        let path = temp_file("new_inode");
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        assert!(watcher.register(&path, 1, true).unwrap());
+        assert!(watcher.register(&path, 2, true).unwrap());
+        assert_eq!(watcher.len(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unregistered_path_is_forgotten() {
+        let path = temp_file("unregister");
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        watcher.register(&path, 1, true).unwrap();
+        assert!(watcher.is_registered_as(&path, 1));
+        assert!(!watcher.is_registered_as(&path, 2));
+        watcher.unregister(&path);
+        assert!(!watcher.is_registered(&path));
+        assert!(watcher.is_empty());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn missing_path_cannot_be_registered() {
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        let path = std::env::temp_dir().join("lw_watcher_test_does_not_exist");
+        assert!(watcher.register(&path, 1, true).is_err());
+        assert!(!watcher.is_registered(&path));
+    }
+
+    #[test]
+    fn least_recently_active_file_is_evicted_when_budget_is_used_up() {
+        let paths: Vec<PathBuf> = ["lru_a", "lru_b", "lru_c"]
+            .iter()
+            .map(|name| temp_file(name))
+            .collect();
+        let mut watcher = KqueueWatcher::new(2).unwrap();
+        watcher.register(&paths[0], 1, true).unwrap();
+        watcher.register(&paths[1], 2, true).unwrap();
+        watcher.touch(&paths[0].to_string_lossy());
+        assert!(watcher.register(&paths[2], 3, true).unwrap());
+        assert!(watcher.is_registered(&paths[0]));
+        assert!(!watcher.is_registered(&paths[1]));
+        assert_eq!(
+            watcher.evicted(),
+            vec![paths[1].to_string_lossy().to_string()]
+        );
+        assert_eq!(
+            watcher.status(),
+            WatchStatus {
+                files: 2,
+                directories: 0,
+                budget: 2,
+                polled: 1,
+                evictions: 1,
+            }
+        );
+        paths.iter().for_each(|path| {
+            let _ = fs::remove_file(path);
+        });
+    }
+
+    #[test]
+    fn evicted_file_is_promoted_back_once_it_changed() {
+        let paths: Vec<PathBuf> = ["promote_a", "promote_b"]
+            .iter()
+            .map(|name| temp_file(name))
+            .collect();
+        let mut watcher = KqueueWatcher::new(1).unwrap();
+        watcher.register(&paths[0], 1, true).unwrap();
+        watcher.register(&paths[1], 2, true).unwrap();
+        assert!(watcher.promote(&paths[0], 1).unwrap());
+        assert!(watcher.is_registered(&paths[0]));
+        assert_eq!(
+            watcher.evicted(),
+            vec![paths[1].to_string_lossy().to_string()]
+        );
+        assert_eq!(watcher.status().evictions, 2);
+        paths.iter().for_each(|path| {
+            let _ = fs::remove_file(path);
+        });
+    }
+
+    #[test]
+    fn unchanged_evicted_file_is_not_registered_again() {
+        let paths: Vec<PathBuf> = ["rewalk_a", "rewalk_b"]
+            .iter()
+            .map(|name| temp_file(name))
+            .collect();
+        let mut watcher = KqueueWatcher::new(1).unwrap();
+        watcher.register(&paths[0], 1, true).unwrap();
+        watcher.register(&paths[1], 2, true).unwrap();
+        // a directory re-walk registers every file in it again
+        assert!(!watcher.register(&paths[0], 1, true).unwrap());
+        assert!(watcher.is_registered(&paths[1]));
+        assert_eq!(
+            watcher.evicted(),
+            vec![paths[0].to_string_lossy().to_string()]
+        );
+        assert_eq!(watcher.status().evictions, 1, "nothing else is evicted");
+        // a replaced file is a new one
+        assert!(watcher.register(&paths[0], 3, true).unwrap());
+        assert_eq!(watcher.status().evictions, 2);
+        paths.iter().for_each(|path| {
+            let _ = fs::remove_file(path);
+        });
+    }
+
+    #[test]
+    fn exited_process_cannot_be_watched() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id() as i32;
+        child.wait().unwrap();
+        let mut watcher = KqueueWatcher::new(0).unwrap();
+        let error = watcher.add_pid(pid).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ESRCH));
+        let path = temp_file("after_exited_pid");
+        assert!(watcher.register(&path, 1, true).unwrap());
+        assert!(watcher.watch().is_ok(), "later watches don't fail on it");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn directories_are_never_evicted() {
+        let path = temp_file("budget_file");
+        let mut watcher = KqueueWatcher::new(1).unwrap();
+        let directory = std::env::temp_dir();
+        assert!(watcher.register(&directory, 1, false).unwrap());
+        assert!(!watcher.register(&path, 2, true).unwrap());
+        assert!(watcher.is_registered(&directory));
+        assert_eq!(watcher.evicted(), vec![path.to_string_lossy().to_string()]);
+        watcher.unregister(&path);
+        assert!(watcher.evicted().is_empty());
+        let _ = fs::remove_file(&path);
+    }
+}